@@ -1,23 +1,101 @@
 use std::{
     ffi::OsStr,
     io::{self, BufReader, Read},
-    process::{Command, ExitStatus, Stdio},
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus, Output, Stdio},
     sync::Arc,
     thread,
+    time::{Duration, Instant},
 };
 
-use crate::progress::{OutputPolicy, ProgressLogger, StepId};
+use crate::{
+    config::IndexConfig,
+    interrupt,
+    progress::{OutputPolicy, OutputStream, ProgressLogger, StepId},
+};
+
+/// Time a timed-out process group gets to exit after SIGTERM before it is killed.
+const TERMINATION_GRACE: Duration = Duration::from_secs(5);
+
+/// Interval at which a command with a timeout is polled for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 fn uv_bin() -> std::ffi::OsString {
     std::env::var_os("_RT_UV_BIN").unwrap_or_else(|| std::ffi::OsString::from("uv"))
 }
 
+/// Run a short uv query and return its trimmed stdout, or `None` if it fails.
+fn uv_query(args: &[&str]) -> Option<String> {
+    let output = Command::new(uv_bin())
+        .args(args)
+        .env("UV_PYTHON_PREFERENCE", "only-managed")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Version reported by `uv --version`, or `"unknown"` when uv cannot be queried.
+#[must_use]
+pub fn uv_version() -> String {
+    uv_query(&["--version"]).unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Resolve a Python request (e.g. `3.12`) to the interpreter uv would use for it.
+///
+/// The canonical path includes the full patch release of managed interpreters.
+#[must_use]
+pub fn find_interpreter(python: &str) -> Option<String> {
+    let path = uv_query(&["python", "find", "--no-config", python])?;
+    let canonical = std::fs::canonicalize(&path)
+        .map_or(path, |resolved| resolved.to_string_lossy().into_owned());
+    Some(canonical)
+}
+
+/// Send SIGTERM to the process group led by `child`, then SIGKILL to whatever is left of it once
+/// the child exited or the grace period elapsed.
+fn terminate_group(child: &mut Child) -> io::Result<ExitStatus> {
+    let pgid = libc::pid_t::try_from(child.id()).map_err(io::Error::other)?;
+    signal_group(pgid, libc::SIGTERM);
+    let deadline = Instant::now() + TERMINATION_GRACE;
+    while child.try_wait()?.is_none() && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    signal_group(pgid, libc::SIGKILL);
+    child.wait()
+}
+
+/// Turn the failure of a command rt sent a signal to stop into an error of kind `Interrupted`.
+fn interrupted(
+    status: io::Result<ExitStatus>,
+    registration: &interrupt::ChildRegistration,
+) -> io::Result<ExitStatus> {
+    match status {
+        Ok(status) if !status.success() && registration.signalled() => {
+            Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"))
+        }
+        status => status,
+    }
+}
+
+fn signal_group(pgid: libc::pid_t, signal: libc::c_int) {
+    // SAFETY: killpg only sends a signal; a group without members reports ESRCH, which is fine.
+    unsafe {
+        libc::killpg(pgid, signal);
+    }
+}
+
 /// A wrapper around `std::process::Command` that captures output and streams it to a
 /// progress sink.
 pub struct ManagedCommand {
     command: Command,
     step_id: StepId,
     sink: Arc<dyn ProgressLogger>,
+    timeout: Option<Duration>,
 }
 
 impl ManagedCommand {
@@ -36,6 +114,7 @@ impl ManagedCommand {
             command,
             step_id,
             sink,
+            timeout: None,
         }
     }
 
@@ -67,6 +146,18 @@ impl ManagedCommand {
         self
     }
 
+    /// Run uv without network access when `offline` is set.
+    #[must_use]
+    pub fn offline(self, offline: bool) -> Self {
+        if offline { self.arg("--offline") } else { self }
+    }
+
+    /// Resolve packages against the index settings of `rt.toml`.
+    #[must_use]
+    pub fn index(self, index: &IndexConfig) -> Self {
+        self.args(index.args())
+    }
+
     /// Set an environment variable for the command.
     #[must_use]
     pub fn env<K, V>(mut self, key: K, val: V) -> Self
@@ -90,20 +181,53 @@ impl ManagedCommand {
         self
     }
 
+    /// Stop the command once it has run for `timeout`.
+    ///
+    /// The command then runs in its own process group, so that everything it spawned, such as
+    /// pytest-xdist workers, is terminated along with it.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Spawn the command, in its own process group if `own_group` is set, and register it so that
+    /// signals received by rt are forwarded to it.
+    fn spawn(&mut self, own_group: bool) -> io::Result<(Child, interrupt::ChildRegistration)> {
+        if own_group {
+            self.command.process_group(0);
+        }
+        let child = self.command.spawn()?;
+        let registration = interrupt::register_child(child.id(), own_group);
+        Ok((child, registration))
+    }
+
     /// Execute the command and wait for it to complete, streaming output to the `DisplayManager`.
     ///
     /// # Errors
     ///
-    /// Returns an error if the child process cannot be spawned or waited on.
+    /// Returns an error if the child process cannot be spawned or waited on, an error of kind
+    /// `TimedOut` if it was stopped after exceeding its timeout, and one of kind `Interrupted` if
+    /// it failed after rt forwarded SIGINT or SIGTERM to it.
     pub fn status(mut self) -> io::Result<ExitStatus> {
-        if self.sink.output_policy() == OutputPolicy::Inherit {
+        let policy = self.sink.output_policy();
+        if policy == OutputPolicy::Inherit {
             self.command.stdout(Stdio::inherit());
             self.command.stderr(Stdio::inherit());
-            return self.command.status();
+            // Without a timeout the command stays in rt's process group, so that it keeps
+            // reading from the terminal and gets Ctrl-C from it directly.
+            let (mut child, registration) = self.spawn(self.timeout.is_some())?;
+            return interrupted(self.wait(&mut child), &registration);
         }
 
-        // Spawn the child process
-        let mut child = self.command.spawn()?;
+        let (mut child, registration) = if policy == OutputPolicy::Tee {
+            // Attached to the terminal like an inherited command; only its output is piped.
+            self.spawn(self.timeout.is_some())?
+        } else {
+            // Spawn the child process in its own group, detached from the terminal
+            self.command.stdin(Stdio::null());
+            self.spawn(true)?
+        };
 
         // Capture stdout and stderr
         let stdout = child
@@ -116,24 +240,55 @@ impl ManagedCommand {
             .ok_or_else(|| io::Error::other("Failed to capture stderr"))?;
 
         // Spawn reader threads
-        let stdout_handle = self.spawn_reader_thread(stdout, "stdout");
-        let stderr_handle = self.spawn_reader_thread(stderr, "stderr");
+        let stdout_handle = self.spawn_reader_thread(stdout, OutputStream::Stdout);
+        let stderr_handle = self.spawn_reader_thread(stderr, OutputStream::Stderr);
 
         // Wait for the child process to complete
-        let status = child.wait()?;
+        let status = self.wait(&mut child);
 
         // Wait for reader threads to finish
         let _ = stdout_handle.join();
         let _ = stderr_handle.join();
 
-        Ok(status)
+        interrupted(status, &registration)
+    }
+
+    /// Wait for `child`, terminating its process group if it outlives the timeout.
+    fn wait(&self, child: &mut Child) -> io::Result<ExitStatus> {
+        let Some(timeout) = self.timeout else {
+            return child.wait();
+        };
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        terminate_group(child)?;
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("timed out after {timeout:?}"),
+        ))
+    }
+
+    /// Execute the command and capture its output instead of streaming it to the progress sink.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the child process cannot be spawned or waited on.
+    pub fn output(mut self) -> io::Result<Output> {
+        self.command.stdin(Stdio::null());
+        let (child, _registration) = self.spawn(true)?;
+        child.wait_with_output()
     }
 
     /// Spawn a thread to read output chunks and stream them to the progress sink.
     fn spawn_reader_thread<R: io::Read + Send + 'static>(
         &self,
         reader: R,
-        _stream_name: &str,
+        stream: OutputStream,
     ) -> thread::JoinHandle<()> {
         let step_id = self.step_id.clone();
         let sink = Arc::clone(&self.sink);
@@ -145,7 +300,7 @@ impl ManagedCommand {
             loop {
                 match buf_reader.read(&mut buffer) {
                     Ok(0) => break,
-                    Ok(n) => sink.append_output_chunk(&step_id, &buffer[..n]),
+                    Ok(n) => sink.append_stream_chunk(&step_id, stream, &buffer[..n]),
                     Err(e) => {
                         sink.append_output(&step_id, format!("[Error reading output: {e}]"));
                         break;
@@ -23,7 +23,13 @@ pub fn run(
 ) -> RtResult<()> {
     let target = resolve_target(venvs, hash)?;
     let ctx_hash = &target.execution_contexts[0].hash;
-    build_selected_contexts(repo, std::slice::from_ref(&target), force_reinstall, false)?;
+    build_selected_contexts(
+        repo,
+        std::slice::from_ref(&target),
+        force_reinstall,
+        false,
+        None,
+    )?;
     let activation_path = activation_path(ctx_hash, &repo.riot_root);
 
     ui::step(format!(
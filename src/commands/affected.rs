@@ -0,0 +1,228 @@
+use std::{path::Path, process::Command};
+
+use indexmap::IndexMap;
+
+use crate::{
+    config::{RepoConfig, Selector},
+    error::{RtError, RtResult},
+    ui,
+    venv::{ExecutionContext, RiotVenv, select_execution_contexts},
+};
+
+/// Git base compared against when `--base` is not given.
+pub const DEFAULT_BASE: &str = "origin/main";
+
+/// Print the hashes of the selected execution contexts affected by the files changed since
+/// `base`, one per line.
+///
+/// # Errors
+///
+/// Returns an error if context selection fails or git cannot list the changed files.
+pub fn run(
+    venvs: IndexMap<String, RiotVenv>,
+    repo: &RepoConfig,
+    selector: Selector,
+    base: &str,
+) -> RtResult<()> {
+    let selected = select_execution_contexts(venvs, selector)?;
+    for venv in select_affected(selected, repo, base)? {
+        for ctx in &venv.execution_contexts {
+            println!("{}", ctx.hash);
+        }
+    }
+    Ok(())
+}
+
+/// Narrow `selected` to the execution contexts affected by the files changed since `base`,
+/// reporting the changed file that selected each of them.
+///
+/// # Errors
+///
+/// Returns an error if git cannot list the changed files.
+pub fn select_affected(
+    selected: Vec<RiotVenv>,
+    repo: &RepoConfig,
+    base: &str,
+) -> RtResult<Vec<RiotVenv>> {
+    let project_root = repo
+        .riotfile_path
+        .parent()
+        .ok_or_else(|| RtError::message("error: could not determine riotfile parent directory"))?;
+    let changed = changed_files(project_root, &repo.riot_root, base)?;
+    ui::step(format!("{} file(s) changed since {base}", changed.len()));
+
+    let mut affected = Vec::new();
+    for mut venv in selected {
+        if let Some(path) = changed.iter().find(|path| {
+            venv.suite_paths
+                .iter()
+                .any(|pattern| glob_match(pattern, path))
+        }) {
+            for ctx in &venv.execution_contexts {
+                ui::detail(format!("{} {} (suitespec: {path})", ctx.hash, venv.name));
+            }
+        } else {
+            venv.execution_contexts.retain(|ctx| {
+                let Some(path) = changed.iter().find(|path| touches_targets(ctx, path)) else {
+                    return false;
+                };
+                ui::detail(format!(
+                    "{} {} (pytest target: {path})",
+                    ctx.hash, venv.name
+                ));
+                true
+            });
+        }
+        if !venv.execution_contexts.is_empty() {
+            affected.push(venv);
+        }
+    }
+
+    let count: usize = affected
+        .iter()
+        .map(|venv| venv.execution_contexts.len())
+        .sum();
+    ui::step(format!("{count} execution context(s) affected"));
+    Ok(affected)
+}
+
+/// Files changed since the merge base of `base` and `HEAD`, including uncommitted and untracked
+/// files, relative to `project_root`. Build output under the riot root is ignored.
+fn changed_files(project_root: &Path, riot_root: &Path, base: &str) -> RtResult<Vec<String>> {
+    let merge_base = git(project_root, &["merge-base", base, "HEAD"])?;
+    let merge_base = merge_base.trim();
+    let exclude = riot_root
+        .strip_prefix(project_root)
+        .map(|relative| format!(":(exclude){}", relative.display()))
+        .unwrap_or_default();
+    let pathspec: &[&str] = if exclude.is_empty() {
+        &[]
+    } else {
+        &["--", ".", &exclude]
+    };
+
+    let mut files: Vec<String> = Vec::new();
+    for args in [
+        &["diff", "--name-only", "--relative", merge_base][..],
+        &["ls-files", "--others", "--exclude-standard"][..],
+    ] {
+        let args = [args, pathspec].concat();
+        files.extend(git(project_root, &args)?.lines().map(str::to_string));
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn git(project_root: &Path, args: &[&str]) -> RtResult<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(project_root)
+        .args(args)
+        .output()
+        .map_err(|err| RtError::message(format!("error: failed to run git: {err}")))?;
+    if !output.status.success() {
+        return Err(RtError::message(format!(
+            "error: git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether `path` is one of the context's pytest targets, lies under one, or is a `conftest.py`
+/// collected for one.
+fn touches_targets(ctx: &ExecutionContext, path: &str) -> bool {
+    let conftest_dir = path
+        .strip_suffix("conftest.py")
+        .filter(|dir| dir.is_empty() || dir.ends_with('/'));
+    ctx.pytest_targets.iter().any(|target| {
+        let target = target.split("::").next().unwrap_or(target);
+        let under_target = path == target
+            || path
+                .strip_prefix(target)
+                .is_some_and(|rest| target.ends_with('/') || rest.starts_with('/'));
+        under_target || conftest_dir.is_some_and(|dir| target.starts_with(dir))
+    })
+}
+
+/// `fnmatch`-style matching as used by suitespec: `*` matches any run of characters, including
+/// `/`, and `?` a single character.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < path.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == path[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    s = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::{glob_match, touches_targets};
+    use crate::venv::ExecutionContext;
+
+    #[test]
+    fn suitespec_patterns_match_like_fnmatch() {
+        assert!(glob_match(
+            "ddtrace/contrib/flask/*",
+            "ddtrace/contrib/flask/patch.py"
+        ));
+        assert!(glob_match(
+            "tests/contrib/*/test_?.py",
+            "tests/contrib/a/b/test_x.py"
+        ));
+        assert!(glob_match("riotfile.py", "riotfile.py"));
+        assert!(!glob_match(
+            "ddtrace/contrib/flask/*",
+            "ddtrace/contrib/flask_cache/x.py"
+        ));
+        assert!(!glob_match("*.pyx", "ddtrace/profiling/x.py"));
+    }
+
+    #[test]
+    fn changed_files_map_through_pytest_targets() {
+        let ctx = ExecutionContext {
+            command: None,
+            pytest_targets: vec![
+                "tests/contrib/flask".to_string(),
+                "tests/test_span.py::TestSpan".to_string(),
+            ],
+            env: IndexMap::new(),
+            create: false,
+            skip_dev_install: false,
+            hash: "2b340ba@13515d1".to_string(),
+        };
+        assert!(touches_targets(&ctx, "tests/contrib/flask/test_views.py"));
+        assert!(touches_targets(&ctx, "tests/test_span.py"));
+        assert!(touches_targets(&ctx, "tests/conftest.py"));
+        assert!(touches_targets(&ctx, "conftest.py"));
+        assert!(!touches_targets(
+            &ctx,
+            "tests/contrib/flask_cache/test_cache.py"
+        ));
+        assert!(!touches_targets(&ctx, "tests/contrib/django/conftest.py"));
+    }
+}
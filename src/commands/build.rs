@@ -1,23 +1,29 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Write as FmtWrite,
-    fs::{self, File},
-    io::{self, IsTerminal, Write},
+    fs,
+    io::{self, Write},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex, OnceLock},
 };
 
 use crate::{
-    command::ManagedCommand,
-    config::Selector,
+    command::{ManagedCommand, find_interpreter, uv_version},
+    config::{IndexConfig, InterpolationVars, Selector, scoped_env},
     error::{RtError, RtResult},
+    manifest::{BuildManifest, digest, file_digest},
+    offline::missing_from_cache,
     progress::{
-        MultiplexedProgressLogger, PlainProgressLogger, ProgressLogger, StepContext, StepId,
-        StepOutcome, Task, TaskRunner, summarize_errors,
+        FailFast, StepContext, StepId, StepOutcome, Task, TaskRunner, default_sink,
+        summarize_errors,
     },
+    riot_root::{StagedDir, TargetLock, lock_target},
+    specifier::normalize_name,
+    step_log::{self, LogPhase},
+    ui,
     venv::{ExecutionContext, RiotVenv, venv_path},
 };
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use itertools::Itertools;
 use tempfile::{Builder, NamedTempFile};
 
@@ -40,9 +46,10 @@ pub fn run(
     selector: Selector,
     force_reinstall: bool,
     no_editable: bool,
+    fail_fast: Option<FailFast>,
 ) -> RtResult<()> {
     let selected = select_execution_contexts(venvs, selector)?;
-    build_selected_contexts(repo, &selected, force_reinstall, no_editable)?;
+    build_selected_contexts(repo, &selected, force_reinstall, no_editable, fail_fast)?;
     Ok(())
 }
 
@@ -61,7 +68,26 @@ pub fn collect_context_indices(selected: &[RiotVenv]) -> Vec<(usize, usize)> {
         .collect()
 }
 
-/// Build every selected execution context and its shared dependencies.
+/// Build step installing the project for `python`.
+#[must_use]
+pub fn dev_install_step(python: &str) -> String {
+    format!("dev install {python}")
+}
+
+/// Build step installing the dependencies with deps key `key`.
+#[must_use]
+pub fn deps_install_step(key: &str) -> String {
+    format!("deps install {key}")
+}
+
+/// Build step creating the execution context `hash`.
+#[must_use]
+pub fn context_step(hash: &str) -> String {
+    format!("create execution context {hash}")
+}
+
+/// Build every selected execution context and its shared dependencies. With `fail_fast`, the
+/// first failed step stops the build.
 ///
 /// # Errors
 ///
@@ -71,6 +97,7 @@ pub fn build_selected_contexts(
     selected: &[RiotVenv],
     force_reinstall: bool,
     no_editable: bool,
+    fail_fast: Option<FailFast>,
 ) -> RtResult<()> {
     if let Err(e) = fs::DirBuilder::new()
         .recursive(true)
@@ -80,49 +107,44 @@ pub fn build_selected_contexts(
             "error: could not create riot root: {e}"
         )));
     }
-    let sink: Arc<dyn ProgressLogger> = if io::stderr().is_terminal() {
-        match MultiplexedProgressLogger::new() {
-            Ok(logger) => Arc::new(logger),
-            Err(_) => Arc::new(PlainProgressLogger::default()),
-        }
-    } else {
-        Arc::new(PlainProgressLogger::default())
-    };
-    let shared = Arc::new(BuildSharedState::new(
-        force_reinstall,
-        no_editable,
-        Arc::clone(&repo.build_env),
-        Arc::clone(&repo.run_env),
-        repo.riot_root.clone(),
-    ));
-    let runner = TaskRunner::new(Arc::clone(&sink)).with_parallelism(Some(current_num_threads()));
+    let sink = step_log::record(default_sink(repo.events), &repo.riot_root, LogPhase::Build);
+    let shared = Arc::new(BuildSharedState::new(repo, force_reinstall, no_editable));
+    let runner = TaskRunner::new(Arc::clone(&sink))
+        .with_parallelism(Some(current_num_threads()))
+        .with_fail_fast(fail_fast);
+
+    if repo.offline {
+        check_offline_cache(&shared, selected, &runner)?;
+    }
 
     let context_indices = collect_context_indices(selected);
 
     let mut dev_pythons: HashSet<String> = HashSet::new();
-    let mut deps_targets: HashSet<usize> = HashSet::new();
+    let mut deps_targets: IndexMap<String, usize> = IndexMap::new();
     for (venv_idx, ctx_idx) in &context_indices {
         let selected_venv = &selected[*venv_idx];
         let exc = &selected_venv.execution_contexts[*ctx_idx];
         if !exc.skip_dev_install {
             dev_pythons.insert(selected_venv.python.clone());
         }
-        deps_targets.insert(*venv_idx);
+        deps_targets
+            .entry(deps_key(selected_venv))
+            .or_insert(*venv_idx);
     }
 
     let mut setup_tasks: Vec<_> = Vec::new();
     setup_tasks.extend(dev_pythons.into_iter().map(|python| {
         let state = Arc::clone(&shared);
-        let step_id = format!("dev install {python}");
+        let step_id = dev_install_step(&python);
         Task::new(StepId::new(&step_id), &step_id, move |ctx| {
             state.ensure_dev_install(&python, &ctx)
         })
     }));
 
-    setup_tasks.extend(deps_targets.into_iter().map(|idx| {
+    setup_tasks.extend(deps_targets.into_iter().map(|(key, idx)| {
         let state = Arc::clone(&shared);
         let venv = &selected[idx];
-        let step_id = format!("deps install {}", venv.hash);
+        let step_id = deps_install_step(&key);
         Task::new(StepId::new(&step_id), &step_id, move |ctx| {
             state.ensure_deps_install(venv, &ctx)
         })
@@ -134,7 +156,7 @@ pub fn build_selected_contexts(
             let state = Arc::clone(&shared);
             let venv = &selected[venv_i];
             let exc_ctx = &venv.execution_contexts[exc_i];
-            let step_id = format!("create execution context {}", exc_ctx.hash);
+            let step_id = context_step(&exc_ctx.hash);
             Task::new(StepId::new(&step_id), &step_id, move |ctx| {
                 state.ensure_execution_ctx(venv, exc_ctx, &ctx)
             })
@@ -152,7 +174,10 @@ pub fn build_selected_contexts(
         ))
     })?;
 
-    if summarize_errors(&setup_errors, "build") {
+    if !setup_errors.is_empty() {
+        let mut errors = setup_errors;
+        errors.extend(runner.skip(exc_ctx_tasks));
+        summarize_errors(&errors, "build");
         return Err(RtError::silent(1));
     }
 
@@ -169,6 +194,86 @@ pub fn build_selected_contexts(
     Ok(())
 }
 
+/// Fail before building anything when an offline build needs interpreters or packages that are
+/// not already on this machine, listing every affected venv.
+fn check_offline_cache(
+    shared: &Arc<BuildSharedState>,
+    selected: &[RiotVenv],
+    runner: &TaskRunner,
+) -> RtResult<()> {
+    let mut targets: IndexMap<String, Vec<&RiotVenv>> = IndexMap::new();
+    let mut dev_pythons: IndexSet<&str> = IndexSet::new();
+    for venv in selected {
+        if !venv.execution_contexts.is_empty() {
+            targets.entry(deps_key(venv)).or_default().push(venv);
+        }
+        if venv
+            .execution_contexts
+            .iter()
+            .any(|ctx| !ctx.skip_dev_install)
+        {
+            dev_pythons.insert(&venv.python);
+        }
+    }
+
+    let mut tasks: Vec<_> = dev_pythons
+        .into_iter()
+        .map(|python| {
+            let state = Arc::clone(shared);
+            let step_id = format!("offline check dev install {python}");
+            Task::new(StepId::new(&step_id), &step_id, move |ctx| {
+                state.check_offline_dev_install(python, &ctx)
+            })
+        })
+        .collect();
+    tasks.extend(targets.into_iter().map(|(key, venvs)| {
+        let state = Arc::clone(shared);
+        let step_id = format!("offline check deps {key}");
+        Task::new(StepId::new(&step_id), &step_id, move |ctx| {
+            state.check_offline_deps(&venvs, &ctx)
+        })
+    }));
+
+    let errors = runner.run(tasks).map_err(|err| {
+        RtError::message(format!(
+            "error: could not configure build parallelism ({err})"
+        ))
+    })?;
+
+    if summarize_errors(&errors, "offline check") {
+        ui::detail("Run the same build once with network access to populate the uv cache.");
+        return Err(RtError::silent(1));
+    }
+
+    Ok(())
+}
+
+/// Build requirements of the project in the current directory, from the `[build-system]` table
+/// of its `pyproject.toml`, defaulting to setuptools like uv does.
+fn build_requirements() -> RtResult<Vec<String>> {
+    let default = || vec!["setuptools>=40.8.0".to_string()];
+    let content = match fs::read_to_string("pyproject.toml") {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(default()),
+        Err(err) => return Err(err.into()),
+    };
+    let pyproject: toml::Table = content
+        .parse()
+        .map_err(|err| RtError::message(format!("error: could not parse pyproject.toml: {err}")))?;
+    let Some(requires) = pyproject
+        .get("build-system")
+        .and_then(|table| table.get("requires"))
+        .and_then(toml::Value::as_array)
+    else {
+        return Ok(default());
+    };
+    Ok(requires
+        .iter()
+        .filter_map(toml::Value::as_str)
+        .map(str::to_string)
+        .collect())
+}
+
 type DynResult<T> = RtResult<T>;
 
 pub struct BuildSharedState {
@@ -177,24 +282,155 @@ pub struct BuildSharedState {
     build_env: Arc<HashMap<String, String>>,
     run_env: Arc<HashMap<String, String>>,
     riot_root: PathBuf,
+    offline: bool,
+    index: Arc<IndexConfig>,
+    vars: InterpolationVars,
+    uv_version: OnceLock<String>,
+    interpreters: Mutex<HashMap<String, String>>,
 }
 
 impl BuildSharedState {
     #[must_use]
-    pub const fn new(
-        force_reinstall: bool,
-        no_editable: bool,
-        build_env: Arc<HashMap<String, String>>,
-        run_env: Arc<HashMap<String, String>>,
-        riot_root: PathBuf,
-    ) -> Self {
+    pub fn new(repo: &RepoConfig, force_reinstall: bool, no_editable: bool) -> Self {
         Self {
             force_reinstall,
             no_editable,
-            build_env,
-            run_env,
-            riot_root,
+            build_env: Arc::clone(&repo.build_env),
+            run_env: Arc::clone(&repo.run_env),
+            riot_root: repo.riot_root.clone(),
+            offline: repo.offline,
+            index: Arc::clone(&repo.index),
+            vars: InterpolationVars::for_repo(repo),
+            uv_version: OnceLock::new(),
+            interpreters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn uv_version(&self) -> &str {
+        self.uv_version.get_or_init(uv_version)
+    }
+
+    /// Digest of the index settings, which may hold credentials and so are never written out.
+    fn index_digest(&self) -> String {
+        digest(self.index.args().join("\0").as_bytes())
+    }
+
+    /// Resolved interpreter for a Python request, cached for the duration of the build.
+    fn resolve_interpreter(&self, python: &str) -> Option<String> {
+        if let Some(resolved) = self.interpreters.lock().unwrap().get(python) {
+            return Some(resolved.clone());
+        }
+        let resolved = find_interpreter(python)?;
+        self.interpreters
+            .lock()
+            .unwrap()
+            .insert(python.to_string(), resolved.clone());
+        Some(resolved)
+    }
+
+    fn interpreter(&self, python: &str) -> String {
+        self.resolve_interpreter(python)
+            .unwrap_or_else(|| format!("{python} (unresolved)"))
+    }
+
+    /// `[env.build]` of rt.toml, overlaid with the `[[venv]]` sections matching `venv` and with
+    /// `${VAR}` references expanded for `vars`. Dev installs are shared across venvs and only
+    /// get the top-level table.
+    fn build_env(
+        &self,
+        vars: &InterpolationVars,
+        venv: Option<&RiotVenv>,
+    ) -> HashMap<String, String> {
+        let env = venv.map_or_else(
+            || (*self.build_env).clone(),
+            |venv| scoped_env(&self.build_env, &venv.build_env),
+        );
+        vars.expand_env(&env)
+    }
+
+    fn dev_install_manifest(&self, python: &str) -> BuildManifest {
+        BuildManifest::new("dev install")
+            .entry("python", python)
+            .entry("interpreter", self.interpreter(python))
+            .entry("uv", self.uv_version())
+            .entry("no_editable", self.no_editable.to_string())
+            .entry("index", self.index_digest())
+            .env_entries(
+                "env.build",
+                &self.build_env(&self.vars.clone().python(python), None),
+            )
+    }
+
+    fn deps_install_manifest(&self, venv: &RiotVenv) -> BuildManifest {
+        BuildManifest::new("deps install")
+            .entry("python", &venv.python)
+            .entry("interpreter", self.interpreter(&venv.python))
+            .entry("uv", self.uv_version())
+            .entry(
+                "requirements",
+                digest(requirements_content(venv).as_bytes()),
+            )
+            .entry("index", self.index_digest())
+            .env_entries(
+                "env.build",
+                &self.build_env(&self.vars.clone().python(&venv.python), Some(venv)),
+            )
+    }
+
+    fn execution_ctx_manifest(
+        &self,
+        venv: &RiotVenv,
+        exc: &ExecutionContext,
+        vars: &InterpolationVars,
+        deps_install_path: &Path,
+        dev_install_path: Option<&PathBuf>,
+    ) -> BuildManifest {
+        let dev_install = dev_install_path.map_or_else(
+            || "none".to_string(),
+            |path| file_digest(&path.join(DONE_MARKER)),
+        );
+        BuildManifest::new("execution context")
+            .entry("python", &venv.python)
+            .entry("interpreter", self.interpreter(&venv.python))
+            .entry("uv", self.uv_version())
+            .entry("deps", file_digest(&deps_install_path.join(DONE_MARKER)))
+            .entry("dev", dev_install)
+            .entry("no_editable", self.no_editable.to_string())
+            .entry("command", exc.command.clone().unwrap_or_default())
+            .entry("services", venv.services.join(","))
+            .env_entries("env.build", &self.build_env(vars, Some(venv)))
+            .env_entries(
+                "env.run",
+                &vars.expand_env(&scoped_env(&self.run_env, &venv.run_env)),
+            )
+    }
+
+    /// Lock `target` against concurrent builds from other rt processes.
+    ///
+    /// While another process holds the lock the step is shown as waiting; callers re-check the
+    /// manifest afterwards since that process has usually just built the same target.
+    fn lock_target(&self, target: &Path, ctx: &StepContext) -> DynResult<TargetLock> {
+        let lock = lock_target(
+            &self.riot_root,
+            target,
+            || {
+                ctx.sink.wait(
+                    &ctx.step_id,
+                    "waiting for another rt process to release the build lock",
+                );
+            },
+            || ctx.cancel.is_cancelled(),
+        )?;
+        if lock.waited() {
+            ctx.sink.start(&ctx.step_id);
         }
+        Ok(lock)
+    }
+
+    /// Whether another process completed the build while this one was taking its lock. A forced
+    /// reinstall only accepts a build finished while it waited for the lock.
+    fn built_meanwhile(&self, lock: &TargetLock, manifest: &BuildManifest, marker: &Path) -> bool {
+        (lock.waited() || !self.force_reinstall) && manifest.is_current(marker)
     }
 
     fn ensure_dev_install(&self, python: &str, ctx: &StepContext) -> DynResult<StepOutcome> {
@@ -202,53 +438,116 @@ impl BuildSharedState {
             get_dev_install_path(&self.riot_root, python, self.no_editable);
 
         let marker_path = dev_install_path.join(DONE_MARKER);
-        if !self.force_reinstall && marker_path.is_file() {
+        let manifest = self.dev_install_manifest(python);
+        if !self.force_reinstall && manifest.is_current(&marker_path) {
             return Ok(StepOutcome::Cached);
         }
 
-        if dev_install_path.exists() {
-            fs::remove_dir_all(&dev_install_path)?;
+        let lock = self.lock_target(&dev_install_path, ctx)?;
+        if self.built_meanwhile(&lock, &manifest, &marker_path) {
+            return Ok(StepOutcome::Cached);
+        }
+
+        let staged = StagedDir::new(&dev_install_path)?;
+
+        let status = self
+            .dev_install_command(python, staged.path(), ctx)
+            .arg("-v")
+            .status()?;
+
+        if !status.success() {
+            return Err(RtError::message(format!(
+                "error: uv pip install failed with status {status}"
+            )));
         }
-        fs::create_dir_all(&dev_install_path)?;
 
-        let mut cmd = ManagedCommand::new_uv("pip", Arc::clone(&ctx.sink), ctx.step_id.clone())
-            .envs(self.build_env.as_ref())
+        manifest.write(&staged.path().join(DONE_MARKER))?;
+        staged.promote()?;
+
+        Ok(StepOutcome::Done)
+    }
+
+    /// `uv pip install` of the project into `target`.
+    fn dev_install_command(
+        &self,
+        python: &str,
+        target: &Path,
+        ctx: &StepContext,
+    ) -> ManagedCommand {
+        let cmd = ManagedCommand::new_uv("pip", Arc::clone(&ctx.sink), ctx.step_id.clone())
+            .offline(self.offline)
+            .envs(self.build_env(&self.vars.clone().python(python), None))
             .env("DD_FAST_BUILD", "1")
             .arg("install")
-            .arg("-v")
+            .index(&self.index)
             .arg("--system")
             .arg("--python")
             .arg(python)
             .arg("--target")
-            .arg(&dev_install_path);
+            .arg(target);
 
-        cmd = if self.no_editable {
+        if self.no_editable {
             cmd.arg(".")
         } else {
             cmd.args(["-e", "."])
                 .args(["--config-setting", "editable_mode=compat"])
-        };
+        }
+    }
 
-        let status = cmd.status()?;
+    /// Check that the build requirements of the project and the packages of its dev install for
+    /// `python` are available without network access.
+    fn check_offline_dev_install(&self, python: &str, ctx: &StepContext) -> DynResult<StepOutcome> {
+        let marker_path =
+            get_dev_install_path(&self.riot_root, python, self.no_editable).join(DONE_MARKER);
+        if !self.force_reinstall && self.dev_install_manifest(python).is_current(&marker_path) {
+            return Ok(StepOutcome::Cached);
+        }
 
-        if !status.success() {
+        let target = tempfile::tempdir()?;
+        let missing = missing_from_cache(&build_requirements()?.join("\n"), |requirements| {
+            let mut file = Builder::new().suffix(".txt").tempfile()?;
+            file.write_all(requirements.as_bytes())?;
+            file.flush()?;
+            ManagedCommand::new_uv("pip", Arc::clone(&ctx.sink), ctx.step_id.clone())
+                .offline(true)
+                .envs(self.build_env(&self.vars.clone().python(python), None))
+                .arg("install")
+                .index(&self.index)
+                .arg("--dry-run")
+                .arg("--system")
+                .arg("--python")
+                .arg(python)
+                .arg("--target")
+                .arg(target.path())
+                .arg("--requirement")
+                .arg(file.path())
+                .output()
+        })?;
+        if !missing.is_empty() {
             return Err(RtError::message(format!(
-                "error: uv pip install failed with status {status}"
+                "error: not in the uv cache: {} (needed to build the dev install for Python {python})",
+                missing.join(", ")
             )));
         }
 
-        File::create(marker_path)?;
-
-        Ok(StepOutcome::Done)
+        // The project itself cannot be dropped from the resolution, so whatever uv reports
+        // missing is needed by its own dependencies.
+        let missing = missing_from_cache(".", |_| {
+            self.dev_install_command(python, target.path(), ctx)
+                .arg("--dry-run")
+                .output()
+        })?;
+        if missing.is_empty() {
+            return Ok(StepOutcome::Done);
+        }
+        Err(RtError::message(format!(
+            "error: not in the uv cache: {} (needed by the dev install for Python {python})",
+            missing.join(", ")
+        )))
     }
 
     fn get_requirements_file(venv: &RiotVenv) -> DynResult<NamedTempFile> {
-        let requirements = if venv.resolved_pkgs.is_empty() {
-            format_requirements(&venv.pkgs)
-        } else {
-            format_resolved_requirements(&venv.resolved_pkgs)
-        }
-        .replace("/home/bits/project", ".");
+        let requirements = requirements_content(venv);
 
         let mut temp = Builder::new().suffix(".txt").tempfile()?;
         temp.write_all(requirements.as_bytes())?;
@@ -257,31 +556,85 @@ impl BuildSharedState {
         Ok(temp)
     }
 
+    /// Check that the interpreter and every package of a deps install shared by `venvs` are
+    /// available without network access.
+    fn check_offline_deps(&self, venvs: &[&RiotVenv], ctx: &StepContext) -> DynResult<StepOutcome> {
+        let venv = venvs[0];
+        let owners = venvs
+            .iter()
+            .map(|venv| format!("{} {} ({})", venv.hash, venv.name, venv.python))
+            .join(", ");
+
+        if self.resolve_interpreter(&venv.python).is_none() {
+            return Err(RtError::message(format!(
+                "error: Python {} is not installed and cannot be downloaded offline (needed by {owners})",
+                venv.python
+            )));
+        }
+
+        let marker_path = get_deps_install_path(&self.riot_root, &deps_key(venv)).join(DONE_MARKER);
+        if !self.force_reinstall && self.deps_install_manifest(venv).is_current(&marker_path) {
+            return Ok(StepOutcome::Cached);
+        }
+
+        let target = tempfile::tempdir()?;
+        let missing = missing_from_cache(&requirements_content(venv), |requirements| {
+            let mut file = Builder::new().suffix(".txt").tempfile()?;
+            file.write_all(requirements.as_bytes())?;
+            file.flush()?;
+            ManagedCommand::new_uv("pip", Arc::clone(&ctx.sink), ctx.step_id.clone())
+                .offline(true)
+                .envs(self.build_env(&self.vars.clone().python(&venv.python), Some(venv)))
+                .arg("install")
+                .index(&self.index)
+                .arg("--dry-run")
+                .arg("--system")
+                .arg("--python")
+                .arg(&venv.python)
+                .arg("--target")
+                .arg(target.path())
+                .arg("--requirement")
+                .arg(file.path())
+                .output()
+        })?;
+
+        if missing.is_empty() {
+            return Ok(StepOutcome::Done);
+        }
+        Err(RtError::message(format!(
+            "error: not in the uv cache: {} (needed by {owners})",
+            missing.join(", ")
+        )))
+    }
+
     fn ensure_deps_install(&self, venv: &RiotVenv, ctx: &StepContext) -> DynResult<StepOutcome> {
-        let mut deps_install_path = self.riot_root.clone();
-        deps_install_path.push(VENV_DEPS_DIR);
-        deps_install_path.push(format!("deps_{}", venv.hash));
+        let deps_install_path = get_deps_install_path(&self.riot_root, &deps_key(venv));
 
         let requirements_file = Self::get_requirements_file(venv)?;
 
         let marker_path = deps_install_path.join(DONE_MARKER);
-        if !self.force_reinstall && marker_path.is_file() {
+        let manifest = self.deps_install_manifest(venv);
+        if !self.force_reinstall && manifest.is_current(&marker_path) {
             return Ok(StepOutcome::Cached);
         }
 
-        if deps_install_path.exists() {
-            fs::remove_dir_all(&deps_install_path)?;
+        let lock = self.lock_target(&deps_install_path, ctx)?;
+        if self.built_meanwhile(&lock, &manifest, &marker_path) {
+            return Ok(StepOutcome::Cached);
         }
-        fs::create_dir_all(&deps_install_path)?;
+
+        let staged = StagedDir::new(&deps_install_path)?;
 
         let status = ManagedCommand::new_uv("pip", Arc::clone(&ctx.sink), ctx.step_id.clone())
-            .envs(self.build_env.as_ref())
+            .offline(self.offline)
+            .envs(self.build_env(&self.vars.clone().python(&venv.python), Some(venv)))
             .arg("install")
+            .index(&self.index)
             .arg("--system")
             .arg("--python")
             .arg(&venv.python)
             .arg("--target")
-            .arg(&deps_install_path)
+            .arg(staged.path())
             .arg("--requirement")
             .arg(requirements_file.path())
             .status()?;
@@ -292,7 +645,8 @@ impl BuildSharedState {
             )));
         }
 
-        File::create(marker_path)?;
+        manifest.write(&staged.path().join(DONE_MARKER))?;
+        staged.promote()?;
 
         Ok(StepOutcome::Done)
     }
@@ -306,24 +660,44 @@ impl BuildSharedState {
         let exc_venv_path = venv_path(&self.riot_root, &exc.hash);
         let marker_path = exc_venv_path.join(DONE_MARKER);
 
-        if !self.force_reinstall && marker_path.is_file() {
+        let deps_install_path = get_deps_install_path(&self.riot_root, &deps_key(venv));
+        let dev_install_path = (!exc.skip_dev_install).then_some(get_dev_install_path(
+            &self.riot_root,
+            &venv.python,
+            self.no_editable,
+        ));
+
+        let vars = self
+            .vars
+            .clone()
+            .python(&venv.python)
+            .venv(&venv.name, &exc.hash);
+        let manifest = self.execution_ctx_manifest(
+            venv,
+            exc,
+            &vars,
+            &deps_install_path,
+            dev_install_path.as_ref(),
+        );
+        if !self.force_reinstall && manifest.is_current(&marker_path) {
             return Ok(StepOutcome::Cached);
         }
 
-        if marker_path.is_file() {
-            fs::remove_file(&marker_path)?;
+        let lock = self.lock_target(&exc_venv_path, ctx)?;
+        if self.built_meanwhile(&lock, &manifest, &marker_path) {
+            return Ok(StepOutcome::Cached);
         }
 
-        let deps_install_path = get_deps_install_path(&self.riot_root, &venv.hash);
-        let dev_install_path = (!exc.skip_dev_install)
-            .then_some(get_dev_install_path(&self.riot_root, &venv.python, self.no_editable));
-
+        // Relocatable so activation scripts do not embed the staging path.
+        let staged = StagedDir::new(&exc_venv_path)?;
         let status = ManagedCommand::new_uv("venv", Arc::clone(&ctx.sink), ctx.step_id.clone())
-            .envs(self.build_env.as_ref())
+            .offline(self.offline)
+            .envs(self.build_env(&vars, Some(venv)))
             .arg("--python")
             .arg(&venv.python)
+            .arg("--relocatable")
             .arg("--clear")
-            .arg(&exc_venv_path)
+            .arg(staged.path())
             .status()?;
 
         if !status.success() {
@@ -332,10 +706,12 @@ impl BuildSharedState {
             )));
         }
 
-        let site_packages_path =
-            exc_venv_path.join(format!("lib/python{}/site-packages", &venv.python));
+        let site_packages_path = staged
+            .path()
+            .join(format!("lib/python{}/site-packages", &venv.python));
         self.configure_site_packages(
             exc,
+            &vars.expand_env(&scoped_env(&self.run_env, &venv.run_env)),
             &deps_install_path,
             dev_install_path.as_ref(),
             &site_packages_path,
@@ -347,9 +723,10 @@ impl BuildSharedState {
             bin_sources.push(dev_install_path);
         }
         bin_sources.push(&deps_install_path);
-        merge_bin_dirs(&exc_venv_path, &bin_sources)?;
+        merge_bin_dirs(staged.path(), &exc_venv_path, &bin_sources)?;
 
-        File::create(marker_path)?;
+        manifest.write(&staged.path().join(DONE_MARKER))?;
+        staged.promote()?;
 
         Ok(StepOutcome::Done)
     }
@@ -357,6 +734,7 @@ impl BuildSharedState {
     fn configure_site_packages(
         &self,
         exc: &ExecutionContext,
+        run_env: &HashMap<String, String>,
         deps_install_path: &Path,
         dev_install_path: Option<&PathBuf>,
         site_packages_path: &Path,
@@ -400,7 +778,14 @@ impl BuildSharedState {
             )?;
         }
 
-        self.write_sitecustomize(site_packages_path, current_dir, &paths, exc, services)?;
+        self.write_sitecustomize(
+            site_packages_path,
+            current_dir,
+            &paths,
+            exc,
+            run_env,
+            services,
+        )?;
 
         Ok(())
     }
@@ -412,6 +797,7 @@ impl BuildSharedState {
         current_dir: &Path,
         paths: &[(std::borrow::Cow<'_, str>, &str)],
         exc: &ExecutionContext,
+        run_env: &HashMap<String, String>,
         services: &[String],
     ) -> RtResult<()> {
         let sitecustomize_path = site_packages_path.join("sitecustomize.py");
@@ -450,9 +836,9 @@ impl BuildSharedState {
         }
 
         // environment variables from rt.toml
-        if !self.run_env.is_empty() {
+        if !run_env.is_empty() {
             sc.push_str("\n# Environment variables from rt.toml\n");
-            for (key, val) in self.run_env.iter() {
+            for (key, val) in run_env {
                 writeln!(sc, "os.environ[r\"{key}\"] = r\"{val}\"")?;
             }
         }
@@ -498,7 +884,8 @@ impl BuildSharedState {
     }
 }
 
-fn get_dev_install_path(riot_root: &Path, python: &str, no_editable: bool) -> PathBuf {
+#[must_use]
+pub fn get_dev_install_path(riot_root: &Path, python: &str, no_editable: bool) -> PathBuf {
     let python_no_dot = python.replace('.', "");
     let suffix = if no_editable { "_noedit" } else { "" };
     let mut dev_install_path = riot_root.to_path_buf();
@@ -507,14 +894,65 @@ fn get_dev_install_path(riot_root: &Path, python: &str, no_editable: bool) -> Pa
     dev_install_path
 }
 
-fn get_deps_install_path(riot_root: &Path, hash: &str) -> PathBuf {
+#[must_use]
+pub fn get_deps_install_path(riot_root: &Path, key: &str) -> PathBuf {
     let mut deps_install_path = riot_root.to_path_buf();
     deps_install_path.push(VENV_DEPS_DIR);
-    deps_install_path.push(format!("deps_{hash}"));
+    deps_install_path.push(format!("deps_{key}"));
     deps_install_path
 }
 
-fn format_requirements(pkgs: &IndexMap<String, String>) -> String {
+/// Requirements installed into the deps directory of a venv: the lockfile pins when available,
+/// the riotfile constraints otherwise, with the packages of its `[[venv]]` sections on top.
+#[must_use]
+pub fn requirements_content(venv: &RiotVenv) -> String {
+    if venv.resolved_pkgs.is_empty() {
+        format_requirements(&venv.installed_pkgs())
+    } else {
+        // A scoped package replaces the pin of the same package.
+        let scoped: HashSet<String> = venv
+            .scoped_pkgs
+            .keys()
+            .map(|name| normalize_name(name))
+            .collect();
+        let pins: IndexMap<String, String> = venv
+            .resolved_pkgs
+            .iter()
+            .filter(|(name, _)| !scoped.contains(&normalize_name(name)))
+            .map(|(name, version)| (name.clone(), version.clone()))
+            .collect();
+        format_resolved_requirements(&pins) + &format_requirements(&venv.scoped_pkgs)
+    }
+    .replace("/home/bits/project", ".")
+}
+
+/// Content address of a deps install: venvs resolving to the same requirements for the same
+/// interpreter share a single `deps_<key>` directory.
+#[must_use]
+pub fn deps_key(venv: &RiotVenv) -> String {
+    // Scoped build env changes what gets built, so it must not share a directory with the rest.
+    let scoped_env = if venv.build_env.is_empty() {
+        String::new()
+    } else {
+        let entries = venv
+            .build_env
+            .iter()
+            .map(|(name, value)| format!("{name}={value}\0"))
+            .join("");
+        format!("\0env.build={}", digest(entries.as_bytes()))
+    };
+    digest(
+        format!(
+            "{}\0{}{scoped_env}",
+            venv.python,
+            requirements_content(venv)
+        )
+        .as_bytes(),
+    )
+}
+
+#[must_use]
+pub fn format_requirements(pkgs: &IndexMap<String, String>) -> String {
     if pkgs.is_empty() {
         return String::new();
     }
@@ -542,16 +980,26 @@ fn format_resolved_requirements(resolved_pkgs: &IndexMap<String, String>) -> Str
             buf.push('\n');
         }
         buf.push_str(lib);
-        buf.push_str("==");
+        // Direct references are kept as `@ url`.
+        buf.push_str(if version.starts_with('@') { " " } else { "==" });
         buf.push_str(version);
     }
     buf.push('\n');
     buf
 }
 
-fn merge_bin_dirs(venv_path: &Path, sources: &[&Path]) -> io::Result<()> {
+/// Copy the scripts of `sources` into the bin directory of the venv staged at `venv_path`,
+/// pointing their shebangs at the python of the venv once it is moved to `final_path`.
+fn merge_bin_dirs(venv_path: &Path, final_path: &Path, sources: &[&Path]) -> io::Result<()> {
     let target_bin = venv_path.join("bin");
-    let absolute_venv = fs::canonicalize(venv_path).unwrap_or_else(|_| venv_path.to_path_buf());
+    let absolute_venv = final_path
+        .parent()
+        .and_then(|parent| fs::canonicalize(parent).ok())
+        .zip(final_path.file_name())
+        .map_or_else(
+            || final_path.to_path_buf(),
+            |(parent, name)| parent.join(name),
+        );
     let python_exe = absolute_venv.join("bin/python");
     let python_shebang = format!("#!{}\n", python_exe.to_string_lossy());
 
@@ -616,3 +1064,44 @@ fn rewrite_python_shebang(content: &[u8], python_shebang: &str) -> Option<Vec<u8
     rewritten.extend_from_slice(rest);
     Some(rewritten)
 }
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+
+    use super::deps_key;
+    use crate::venv::RiotVenv;
+
+    #[test]
+    fn deps_key_separates_scoped_build_env() {
+        let venv = |hash: &str, cflags: Option<&str>| {
+            let mut pkgs = IndexMap::new();
+            pkgs.insert("flask".to_string(), "~=2.0".to_string());
+            let mut venv = RiotVenv::new(
+                "flask".to_string(),
+                "3.12".to_string(),
+                pkgs,
+                hash.to_string(),
+                Vec::new(),
+            );
+            if let Some(cflags) = cflags {
+                venv.build_env
+                    .insert("CFLAGS".to_string(), cflags.to_string());
+            }
+            venv
+        };
+
+        assert_eq!(
+            deps_key(&venv("2b340ba", None)),
+            deps_key(&venv("30a07c0", None))
+        );
+        assert_ne!(
+            deps_key(&venv("2b340ba", None)),
+            deps_key(&venv("30a07c0", Some("-O0")))
+        );
+        assert_ne!(
+            deps_key(&venv("2b340ba", Some("-O2"))),
+            deps_key(&venv("30a07c0", Some("-O0")))
+        );
+    }
+}
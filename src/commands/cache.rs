@@ -0,0 +1,297 @@
+use std::path::Path;
+
+use crossterm::style::Stylize;
+use indexmap::{IndexMap, IndexSet};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::to_string_pretty;
+
+use crate::{
+    config::RepoConfig,
+    error::{RtError, RtResult},
+    riot_root::{self, EntryKind, disk_usage, format_size, references},
+    venv::RiotVenv,
+};
+
+/// Venvs listed by name before the rest are summarized as a count.
+const MAX_OWNER_LABELS: usize = 3;
+
+#[derive(Serialize)]
+struct JsonOwner {
+    hash: String,
+    name: String,
+    python: String,
+}
+
+#[derive(Serialize)]
+struct JsonEntry {
+    path: String,
+    layer: &'static str,
+    bytes: u64,
+    referenced: bool,
+    venvs: Vec<JsonOwner>,
+}
+
+#[derive(Serialize)]
+struct JsonReport {
+    riot_root: String,
+    total_bytes: u64,
+    unreferenced_bytes: u64,
+    layers: IndexMap<&'static str, u64>,
+    entries: Vec<JsonEntry>,
+}
+
+struct UsageEntry<'a> {
+    kind: EntryKind,
+    name: String,
+    path: String,
+    bytes: u64,
+    owners: Vec<&'a RiotVenv>,
+}
+
+/// Report the disk usage of every build directory under the riot root, grouped by layer and
+/// mapped back to the venvs of the current riotfile.
+///
+/// # Errors
+///
+/// Returns an error if the riot root cannot be scanned or the JSON report cannot be serialized.
+pub fn disk_usage_report(
+    venvs: &IndexMap<String, RiotVenv>,
+    repo: &RepoConfig,
+    json: bool,
+) -> RtResult<()> {
+    let entries = usage_entries(venvs, &repo.riot_root)?;
+
+    let mut layers: IndexMap<&'static str, u64> = [
+        EntryKind::ExecutionContext,
+        EntryKind::Deps,
+        EntryKind::DevInstall,
+    ]
+    .into_iter()
+    .map(|kind| (kind.label(), 0))
+    .collect();
+    let mut total_bytes = 0u64;
+    let mut unreferenced_bytes = 0u64;
+    for entry in &entries {
+        *layers.entry(entry.kind.label()).or_default() += entry.bytes;
+        total_bytes += entry.bytes;
+        if entry.owners.is_empty() {
+            unreferenced_bytes += entry.bytes;
+        }
+    }
+
+    if json {
+        let report = JsonReport {
+            riot_root: repo.riot_root.display().to_string(),
+            total_bytes,
+            unreferenced_bytes,
+            layers,
+            entries: entries.into_iter().map(JsonEntry::from).collect(),
+        };
+        let output = to_string_pretty(&report).map_err(|err| {
+            RtError::message(format!(
+                "error: failed to serialize disk usage as JSON: {err}"
+            ))
+        })?;
+        println!("{output}");
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No builds found under {}", repo.riot_root.display());
+        return Ok(());
+    }
+
+    let name_width = entries
+        .iter()
+        .map(|entry| entry.name.len())
+        .max()
+        .unwrap_or(0);
+    for entry in &entries {
+        let owners = if entry.owners.is_empty() {
+            "unreferenced".yellow().to_string()
+        } else {
+            owner_labels(&entry.owners)
+        };
+        println!(
+            "{:>10}  {:<11}  {:<name_width$}  {owners}",
+            format_size(entry.bytes),
+            entry.kind.label(),
+            entry.name,
+        );
+    }
+
+    println!();
+    for (label, bytes) in &layers {
+        println!("{:>10}  {label}", format_size(*bytes));
+    }
+    println!("{:>10}  total", format_size(total_bytes));
+    if unreferenced_bytes > 0 {
+        println!(
+            "{:>10}  unreferenced (reclaim with `rt gc`)",
+            format_size(unreferenced_bytes)
+        );
+    }
+
+    Ok(())
+}
+
+/// Every build directory under the riot root with its size and the venvs referencing it,
+/// ordered by layer and then by decreasing size.
+fn usage_entries<'a>(
+    venvs: &'a IndexMap<String, RiotVenv>,
+    riot_root: &Path,
+) -> RtResult<Vec<UsageEntry<'a>>> {
+    let refs = references(riot_root, venvs);
+    let mut entries: Vec<UsageEntry<'a>> = riot_root::scan(riot_root)?
+        .into_par_iter()
+        .filter(|entry| entry.kind != EntryKind::Lockfile)
+        .map(|entry| {
+            let owners = refs
+                .get(&entry.path)
+                .map(|hashes| hashes.iter().filter_map(|hash| venvs.get(hash)).collect())
+                .unwrap_or_default();
+            UsageEntry {
+                kind: entry.kind,
+                name: entry
+                    .path
+                    .strip_prefix(riot_root)
+                    .unwrap_or(&entry.path)
+                    .display()
+                    .to_string(),
+                path: entry.path.display().to_string(),
+                bytes: disk_usage(&entry.path),
+                owners,
+            }
+        })
+        .collect();
+    entries.sort_by(|left, right| {
+        left.kind
+            .cmp(&right.kind)
+            .then_with(|| right.bytes.cmp(&left.bytes))
+            .then_with(|| left.name.cmp(&right.name))
+    });
+
+    Ok(entries)
+}
+
+impl From<UsageEntry<'_>> for JsonEntry {
+    fn from(entry: UsageEntry<'_>) -> Self {
+        Self {
+            path: entry.path,
+            layer: entry.kind.label(),
+            bytes: entry.bytes,
+            referenced: !entry.owners.is_empty(),
+            venvs: entry
+                .owners
+                .iter()
+                .map(|venv| JsonOwner {
+                    hash: venv.hash.clone(),
+                    name: venv.name.clone(),
+                    python: venv.python.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Describe the venvs using an entry as `name (python)`, collapsing long lists.
+fn owner_labels(owners: &[&RiotVenv]) -> String {
+    let labels: IndexSet<String> = owners
+        .iter()
+        .map(|venv| format!("{} ({})", venv.name, venv.python))
+        .collect();
+    let shown: Vec<&str> = labels
+        .iter()
+        .take(MAX_OWNER_LABELS)
+        .map(String::as_str)
+        .collect();
+    let hidden = labels.len().saturating_sub(MAX_OWNER_LABELS);
+    if hidden == 0 {
+        shown.join(", ")
+    } else {
+        format!("{}, +{hidden} more", shown.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use indexmap::IndexMap;
+
+    use super::{JsonEntry, usage_entries};
+    use crate::{
+        commands::build::{deps_key, get_deps_install_path},
+        riot_root::EntryKind,
+        venv::{ExecutionContext, RiotVenv, lockfile_path, venv_path},
+    };
+
+    #[test]
+    fn maps_entries_to_their_venvs_and_flags_unreferenced_ones() {
+        let root = tempfile::tempdir().unwrap();
+        let venvs: IndexMap<String, RiotVenv> = [("2b340ba", "flask"), ("30a07c0", "django")]
+            .into_iter()
+            .map(|(hash, name)| {
+                let mut venv = RiotVenv::new(
+                    name.to_string(),
+                    "3.12".to_string(),
+                    IndexMap::new(),
+                    hash.to_string(),
+                    Vec::new(),
+                );
+                venv.execution_contexts.push(ExecutionContext {
+                    command: Some("pytest".to_string()),
+                    pytest_targets: Vec::new(),
+                    env: IndexMap::new(),
+                    create: false,
+                    skip_dev_install: false,
+                    hash: format!("{hash}@13515d1"),
+                });
+                (hash.to_string(), venv)
+            })
+            .collect();
+        let context = venv_path(root.path(), "2b340ba@13515d1");
+        let shared = get_deps_install_path(root.path(), &deps_key(&venvs["2b340ba"]));
+        let orphan = venv_path(root.path(), "4f1c2d3@13515d1");
+        for dir in [&context, &shared, &orphan] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(context.join("data"), "0123456789").unwrap();
+        // Lockfiles are not builds and stay out of the report.
+        let lockfile = lockfile_path(root.path(), "2b340ba");
+        fs::create_dir_all(lockfile.parent().unwrap()).unwrap();
+        fs::write(&lockfile, "flask==2.3.3\n").unwrap();
+
+        let entries = usage_entries(&venvs, root.path()).unwrap();
+        let summary: Vec<(EntryKind, &str, Vec<&str>)> = entries
+            .iter()
+            .map(|entry| {
+                let owners = entry.owners.iter().map(|venv| venv.name.as_str()).collect();
+                (entry.kind, entry.name.as_str(), owners)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    EntryKind::ExecutionContext,
+                    "venv_2b340ba_13515d1",
+                    vec!["flask"]
+                ),
+                (EntryKind::ExecutionContext, "venv_4f1c2d3_13515d1", vec![]),
+                (
+                    EntryKind::Deps,
+                    shared.strip_prefix(root.path()).unwrap().to_str().unwrap(),
+                    vec!["flask", "django"]
+                ),
+            ]
+        );
+
+        let referenced: Vec<bool> = entries
+            .into_iter()
+            .map(|entry| JsonEntry::from(entry).referenced)
+            .collect();
+        assert_eq!(referenced, [true, false, true]);
+    }
+}
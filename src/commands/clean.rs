@@ -1,21 +1,80 @@
-use std::{fmt::Write as _, fs, path::Path};
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use indexmap::{IndexMap, IndexSet};
 
 use crate::{
-    constants::VENV_PREFIX,
+    commands::build::{deps_key, get_deps_install_path, get_dev_install_path},
+    config::{RepoConfig, Selector},
+    constants::DONE_MARKER,
     error::{RtError, RtResult},
+    riot_root::{self, EntryKind, references, remove_entry},
     ui,
+    venv::{RiotVenv, lockfile_path, select_execution_contexts, venv_path},
 };
 
-/// Remove virtual environments created under the riot root while keeping compiled requirements.
+/// Which layers of the riot root `rt clean` removes.
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CleanLayers {
+    pub contexts: bool,
+    pub deps: bool,
+    pub dev_install: bool,
+    pub locks: bool,
+}
+
+impl CleanLayers {
+    /// Layers removed when no layer flag is given.
+    ///
+    /// A selector only drops the matched venvs' own builds; the dev install is shared by every
+    /// venv of a Python version, so it is only included when cleaning everything.
+    const fn or_default(self, selective: bool) -> Self {
+        if self.contexts || self.deps || self.dev_install || self.locks {
+            return self;
+        }
+        Self {
+            contexts: true,
+            deps: true,
+            dev_install: !selective,
+            locks: false,
+        }
+    }
+
+    const fn includes(self, kind: EntryKind) -> bool {
+        match kind {
+            EntryKind::ExecutionContext => self.contexts,
+            EntryKind::Deps => self.deps,
+            EntryKind::DevInstall => self.dev_install,
+            EntryKind::Lockfile => self.locks,
+            // Leftovers of interrupted builds are never worth keeping.
+            EntryKind::Staging => true,
+        }
+    }
+}
+
+/// Remove builds under the riot root, optionally narrowed to the venvs matched by `selector`,
+/// to specific layers and to entries whose done marker is older than `older_than`.
+///
+/// Without a selector every entry of the chosen layers is removed, whether or not the riotfile
+/// still references it. Lockfiles are only removed when `--locks` is requested.
 ///
 /// # Errors
 ///
-/// Returns an error if directory traversal or removal fails.
-pub fn run(riot_root: &Path) -> RtResult<()> {
-    ui::step(format!(
-        "Cleaning virtual environments under {}",
-        riot_root.display()
-    ));
+/// Returns an error if context selection fails, the riot root cannot be scanned, or removal fails.
+pub fn run(
+    venvs: IndexMap<String, RiotVenv>,
+    repo: &RepoConfig,
+    selector: Option<Selector>,
+    layers: CleanLayers,
+    older_than: Option<Duration>,
+) -> RtResult<()> {
+    let riot_root = &repo.riot_root;
+    ui::step(format!("Cleaning builds under {}", riot_root.display()));
 
     if !riot_root.exists() {
         ui::detail("Riot root not found, nothing to clean.");
@@ -23,43 +82,27 @@ pub fn run(riot_root: &Path) -> RtResult<()> {
         return Ok(());
     }
 
-    let mut targets = Vec::new();
-    let entries = fs::read_dir(riot_root).map_err(|err| {
-        RtError::message(format!(
-            "error: failed to read riot root {}: {err}",
-            riot_root.display()
-        ))
-    })?;
-
-    for entry_result in entries {
-        let entry = entry_result.map_err(|err| {
-            RtError::message(format!("error: failed to evaluate riot root entry: {err}"))
-        })?;
-        let file_type = entry.file_type().map_err(|err| {
-            RtError::message(format!(
-                "error: could not inspect entry {}: {err}",
-                entry.path().display()
-            ))
-        })?;
-
-        if !file_type.is_dir() {
-            continue;
-        }
+    let layers = layers.or_default(selector.is_some());
+    let mut targets = match selector {
+        Some(selector) => selected_targets(venvs, riot_root, selector, layers)?,
+        None => riot_root::scan(riot_root)?
+            .into_iter()
+            .filter(|entry| layers.includes(entry.kind))
+            .map(|entry| entry.path)
+            .collect(),
+    };
 
-        let name = entry.file_name();
-        let name = name.to_string_lossy();
-        if name == "requirements" {
-            continue;
-        }
-        if !name.starts_with(VENV_PREFIX) {
-            continue;
-        }
-
-        targets.push(entry.path());
+    if let Some(older_than) = older_than {
+        let now = SystemTime::now();
+        targets.retain(|path| {
+            built_at(path)
+                .and_then(|built| now.duration_since(built).ok())
+                .is_some_and(|age| age >= older_than)
+        });
     }
 
     if targets.is_empty() {
-        ui::detail("No cached virtual environments were found.");
+        ui::detail("No matching builds were found.");
         ui::blank_line();
         return Ok(());
     }
@@ -70,7 +113,7 @@ pub fn run(riot_root: &Path) -> RtResult<()> {
 
     for target in &targets {
         ui::detail(format!("Removing {}", target.display()));
-        if let Err(err) = fs::remove_dir_all(target) {
+        if let Err(err) = remove_entry(target) {
             failures.push((target.display().to_string(), err));
         }
     }
@@ -81,10 +124,123 @@ pub fn run(riot_root: &Path) -> RtResult<()> {
         Ok(())
     } else {
         let mut message =
-            String::from("error: failed to remove the following directories while cleaning:");
+            String::from("error: failed to remove the following entries while cleaning:");
         for (path, err) in failures {
             let _ = write!(&mut message, "\n- {path}: {err}");
         }
         Err(RtError::message(message))
     }
 }
+
+/// Existing paths of the requested layers that belong to the venvs matched by `selector`.
+fn selected_targets(
+    venvs: IndexMap<String, RiotVenv>,
+    riot_root: &Path,
+    selector: Selector,
+    layers: CleanLayers,
+) -> RtResult<Vec<PathBuf>> {
+    let context_counts: IndexMap<String, usize> = venvs
+        .iter()
+        .map(|(hash, venv)| (hash.clone(), venv.execution_contexts.len()))
+        .collect();
+    let refs = references(riot_root, &venvs);
+    let selected = select_execution_contexts(venvs, selector)?;
+    let selected_hashes: HashSet<&str> = selected.iter().map(|venv| venv.hash.as_str()).collect();
+
+    let mut targets = IndexSet::new();
+    for venv in &selected {
+        if layers.contexts {
+            for ctx in &venv.execution_contexts {
+                targets.insert(venv_path(riot_root, &ctx.hash));
+            }
+            // The context `rt shell <venv hash>` builds only goes with the whole venv.
+            if context_counts.get(&venv.hash) == Some(&venv.execution_contexts.len()) {
+                targets.insert(venv_path(riot_root, &venv.hash));
+            }
+        }
+        if layers.deps {
+            // Deps installs are shared by venvs with the same requirements; keep the ones a venv
+            // left out of the selection still uses.
+            let path = get_deps_install_path(riot_root, &deps_key(venv));
+            if refs.get(&path).is_none_or(|owners| {
+                owners
+                    .iter()
+                    .all(|owner| selected_hashes.contains(owner.as_str()))
+            }) {
+                targets.insert(path);
+            }
+        }
+        if layers.dev_install {
+            for no_editable in [false, true] {
+                targets.insert(get_dev_install_path(riot_root, &venv.python, no_editable));
+            }
+        }
+        if layers.locks {
+            targets.insert(lockfile_path(riot_root, &venv.hash));
+        }
+    }
+
+    Ok(targets.into_iter().filter(|path| path.exists()).collect())
+}
+
+/// When a build was completed: the done marker's mtime, falling back to the entry's own mtime.
+fn built_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path.join(DONE_MARKER))
+        .or_else(|_| fs::metadata(path))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use indexmap::IndexMap;
+
+    use super::{CleanLayers, selected_targets};
+    use crate::{
+        commands::build::{deps_key, get_deps_install_path},
+        config::Selector,
+        venv::RiotVenv,
+    };
+
+    #[test]
+    fn selective_clean_keeps_deps_shared_with_unselected_venvs() {
+        let root = tempfile::tempdir().unwrap();
+        let venvs: IndexMap<String, RiotVenv> = [("2b340ba", "flask"), ("30a07c0", "django")]
+            .into_iter()
+            .map(|(hash, name)| {
+                let venv = RiotVenv::new(
+                    name.to_string(),
+                    "3.12".to_string(),
+                    IndexMap::new(),
+                    hash.to_string(),
+                    Vec::new(),
+                );
+                (hash.to_string(), venv)
+            })
+            .collect();
+        let shared = get_deps_install_path(root.path(), &deps_key(&venvs["2b340ba"]));
+        assert_eq!(
+            shared,
+            get_deps_install_path(root.path(), &deps_key(&venvs["30a07c0"]))
+        );
+        fs::create_dir_all(&shared).unwrap();
+
+        let layers = CleanLayers {
+            deps: true,
+            ..CleanLayers::default()
+        };
+        let clean = |pattern: &str| {
+            selected_targets(
+                venvs.clone(),
+                root.path(),
+                Selector::Pattern(pattern.to_string()),
+                layers,
+            )
+            .unwrap()
+        };
+        assert!(clean("2b340ba").is_empty());
+        assert_eq!(clean("flask|django"), [shared]);
+    }
+}
@@ -0,0 +1,81 @@
+use crossterm::style::{Attribute, Stylize};
+use indexmap::IndexMap;
+
+use crate::config::{ConfigLayer, ConfigSource};
+
+/// Print the configuration files rt reads, then every effective setting with the source it
+/// comes from.
+///
+/// Precedence, from lowest to highest: user config, project `rt.toml`, command line. Env tables
+/// and `[index]` are merged key by key, and `[[venv]]` sections of both files all apply.
+pub fn show(layers: &[ConfigLayer], offline: bool) {
+    println!("{}", "Configuration files".bold().cyan());
+    for layer in layers {
+        let path = layer.path.as_ref().map_or_else(
+            || "<unknown location>".to_string(),
+            |path| path.display().to_string(),
+        );
+        let status = if layer.exists() {
+            String::new()
+        } else {
+            format!(" {}", "(not found)".attribute(Attribute::Dim))
+        };
+        println!("  {:<8} {path}{status}", layer.source.label());
+    }
+    println!();
+
+    let mut effective: IndexMap<String, (toml::Value, ConfigSource)> = IndexMap::new();
+    for layer in layers {
+        for (key, value) in layer.settings.settings() {
+            effective.insert(key, (value, layer.source));
+        }
+    }
+    if offline {
+        effective.insert(
+            "offline".to_string(),
+            (toml::Value::Boolean(true), ConfigSource::CommandLine),
+        );
+    }
+
+    println!("{}", "Effective settings".bold().cyan());
+    if effective.is_empty() {
+        println!("  {}", "<none>".attribute(Attribute::Dim));
+    } else {
+        effective.sort_keys();
+        print_settings(&effective, 2);
+    }
+
+    // Every [[venv]] section applies, user sections first, so later ones win on conflicts.
+    for layer in layers {
+        for scope in &layer.settings.venvs {
+            println!();
+            println!(
+                "{} {}",
+                "[[venv]]".bold().cyan(),
+                format!("# {}", layer.source.label()).attribute(Attribute::Dim)
+            );
+            let settings = scope
+                .settings()
+                .into_iter()
+                .map(|(key, value)| (key, (value, layer.source)))
+                .collect();
+            print_settings(&settings, 2);
+        }
+    }
+}
+
+fn print_settings(settings: &IndexMap<String, (toml::Value, ConfigSource)>, indent: usize) {
+    let width = settings
+        .iter()
+        .map(|(key, (value, _))| key.len() + value.to_string().len() + 3)
+        .max()
+        .unwrap_or(0);
+    for (key, (value, source)) in settings {
+        println!(
+            "{:indent$}{:<width$}  {}",
+            "",
+            format!("{key} = {value}"),
+            format!("# {}", source.label()).attribute(Attribute::Dim)
+        );
+    }
+}
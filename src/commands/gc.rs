@@ -0,0 +1,162 @@
+use std::{fmt::Write as _, path::Path};
+
+use indexmap::IndexMap;
+
+use crate::{
+    config::RepoConfig,
+    error::{RtError, RtResult},
+    riot_root::{self, RootEntry, disk_usage, format_size, references, remove_entry},
+    ui,
+    venv::RiotVenv,
+};
+
+/// Remove build directories and lockfiles that no venv of the current riotfile references.
+///
+/// # Errors
+///
+/// Returns an error if the riot root cannot be scanned or an entry cannot be removed.
+pub fn run(venvs: &IndexMap<String, RiotVenv>, repo: &RepoConfig, dry_run: bool) -> RtResult<()> {
+    ui::step(format!(
+        "Collecting unreferenced entries under {}",
+        repo.riot_root.display()
+    ));
+
+    let orphans = orphans(&repo.riot_root, venvs)?;
+
+    if orphans.is_empty() {
+        ui::detail("Nothing to collect.");
+        ui::blank_line();
+        return Ok(());
+    }
+
+    let mut total = 0u64;
+    let mut failures = Vec::new();
+    for entry in &orphans {
+        let size = disk_usage(&entry.path);
+        total += size;
+        ui::detail(format!(
+            "{:>10}  {:<11}  {}",
+            format_size(size),
+            entry.kind.label(),
+            entry.path.display()
+        ));
+        if !dry_run && let Err(err) = remove_entry(&entry.path) {
+            failures.push((entry.path.display().to_string(), err));
+        }
+    }
+
+    let count = orphans.len();
+    let plural = if count == 1 { "y" } else { "ies" };
+    if dry_run {
+        ui::detail(format!(
+            "Would free {} across {count} entr{plural} (dry run).",
+            format_size(total)
+        ));
+    } else {
+        ui::detail(format!(
+            "Freed {} across {count} entr{plural}.",
+            format_size(total)
+        ));
+    }
+    ui::blank_line();
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        let mut message = String::from("error: failed to remove the following entries:");
+        for (path, err) in failures {
+            let _ = write!(&mut message, "\n- {path}: {err}");
+        }
+        Err(RtError::message(message))
+    }
+}
+
+/// Entries under `riot_root` that none of `venvs` references.
+fn orphans(riot_root: &Path, venvs: &IndexMap<String, RiotVenv>) -> RtResult<Vec<RootEntry>> {
+    let refs = references(riot_root, venvs);
+    Ok(riot_root::scan(riot_root)?
+        .into_iter()
+        .filter(|entry| !refs.contains_key(&entry.path))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use indexmap::IndexMap;
+
+    use super::orphans;
+    use crate::{
+        commands::build::{deps_key, get_deps_install_path, get_dev_install_path},
+        constants::VENV_DEPS_DIR,
+        riot_root::{EntryKind, lock_target},
+        venv::{ExecutionContext, RiotVenv, lockfile_path, venv_path},
+    };
+
+    #[test]
+    fn collects_only_unreferenced_entries() {
+        let root = tempfile::tempdir().unwrap();
+        let mut venv = RiotVenv::new(
+            "flask".to_string(),
+            "3.12".to_string(),
+            IndexMap::from([("flask".to_string(), "==2.3.3".to_string())]),
+            "2b340ba".to_string(),
+            Vec::new(),
+        );
+        venv.execution_contexts.push(ExecutionContext {
+            command: Some("pytest".to_string()),
+            pytest_targets: Vec::new(),
+            env: IndexMap::new(),
+            create: false,
+            skip_dev_install: false,
+            hash: "2b340ba@13515d1".to_string(),
+        });
+        let venvs = IndexMap::from([(venv.hash.clone(), venv)]);
+        let venv = &venvs["2b340ba"];
+
+        let referenced = [
+            venv_path(root.path(), "2b340ba@13515d1"),
+            get_deps_install_path(root.path(), &deps_key(venv)),
+            get_dev_install_path(root.path(), "3.12", false),
+        ];
+        let orphan_dirs = [
+            venv_path(root.path(), "30a07c0@13515d1"),
+            root.path().join(VENV_DEPS_DIR).join("deps_0123"),
+            get_dev_install_path(root.path(), "3.9", false),
+        ];
+        // Build in progress in another process: kept even though nothing references it.
+        let building = root.path().join(VENV_DEPS_DIR).join("deps_4567");
+        let staging = root.path().join(VENV_DEPS_DIR).join("deps_4567.tmp-1");
+        let abandoned = root.path().join(VENV_DEPS_DIR).join("deps_89ab.tmp-1");
+        for dir in referenced
+            .iter()
+            .chain(&orphan_dirs)
+            .chain([&staging, &abandoned])
+        {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let lockfile = lockfile_path(root.path(), "2b340ba");
+        let stale_lockfile = lockfile_path(root.path(), "30a07c0");
+        fs::create_dir_all(lockfile.parent().unwrap()).unwrap();
+        fs::write(&lockfile, "flask==2.3.3\n").unwrap();
+        fs::write(&stale_lockfile, "django==4.2\n").unwrap();
+        let _lock = lock_target(root.path(), &building, || {}, || false).unwrap();
+
+        let mut found: Vec<_> = orphans(root.path(), &venvs)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.kind, entry.path))
+            .collect();
+        found.sort();
+        let mut expected = vec![
+            (EntryKind::ExecutionContext, orphan_dirs[0].clone()),
+            (EntryKind::Deps, orphan_dirs[1].clone()),
+            (EntryKind::DevInstall, orphan_dirs[2].clone()),
+            (EntryKind::Lockfile, stale_lockfile),
+            (EntryKind::Staging, abandoned),
+        ];
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+}
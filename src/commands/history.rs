@@ -0,0 +1,127 @@
+use std::time::{Duration, SystemTime};
+
+use crossterm::style::{Attribute, Color, Stylize};
+use serde_json::to_string_pretty;
+
+use crate::{
+    config::RepoConfig,
+    error::{RtError, RtResult},
+    history::{self, ContextStatus, RunRecord},
+};
+
+/// Print the most recent `limit` runs recorded under the riot root, newest first.
+///
+/// # Errors
+///
+/// Returns an error if the history cannot be read or the JSON report cannot be serialized.
+pub fn run(repo: &RepoConfig, limit: usize, json: bool) -> RtResult<()> {
+    let runs = history::load(&repo.riot_root)?;
+    let recent: Vec<&RunRecord> = runs.iter().rev().take(limit).collect();
+
+    if json {
+        let output = to_string_pretty(&recent).map_err(|err| {
+            RtError::message(format!(
+                "error: failed to serialize run history as JSON: {err}"
+            ))
+        })?;
+        println!("{output}");
+        return Ok(());
+    }
+
+    if recent.is_empty() {
+        println!("No runs recorded under {}", repo.riot_root.display());
+        return Ok(());
+    }
+
+    let now = history::epoch_secs(SystemTime::now());
+    for (index, record) in recent.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        let failed = record.failed().count();
+        let mut summary = if failed == 0 {
+            format!("{} passed", record.results.len())
+                .green()
+                .to_string()
+        } else {
+            format!("{failed}/{} failed", record.results.len())
+                .red()
+                .to_string()
+        };
+        let flaky = record
+            .results
+            .iter()
+            .filter(|result| result.status == ContextStatus::Flaky)
+            .count();
+        if flaky > 0 {
+            summary = format!("{summary}, {}", format!("{flaky} flaky").dark_magenta());
+        }
+        let mut invocation = record.cmdargs.join(" ");
+        if let Some(command) = &record.command_override {
+            invocation = format!("--command {command:?} {invocation}");
+        }
+        println!(
+            "{}  {summary}  {}  {}",
+            format_ago(now - record.started_at).bold().cyan(),
+            format_duration(record.duration_secs),
+            invocation.trim_end().attribute(Attribute::Dim),
+        );
+
+        let name_width = record
+            .results
+            .iter()
+            .map(|result| result.name.len() + result.python.len() + 3)
+            .max()
+            .unwrap_or(0);
+        for result in &record.results {
+            let status = format!("{:<12}", result.status.label()).with(status_color(result.status));
+            let exit = if result.exit_code != 0 {
+                format!("exit {}", result.exit_code)
+            } else if result.attempts > 1 {
+                format!("attempt {}", result.attempts)
+            } else {
+                String::new()
+            };
+            println!(
+                "  {status}{exit:<9}{:>7}  {}  {:<name_width$}  {}",
+                format_duration(result.duration_secs),
+                result.hash,
+                format!("{} ({})", result.name, result.python),
+                result.command.trim_end().attribute(Attribute::Dim),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+const fn status_color(status: ContextStatus) -> Color {
+    match status {
+        ContextStatus::Passed => Color::Green,
+        ContextStatus::Skipped => Color::DarkYellow,
+        ContextStatus::Flaky => Color::DarkMagenta,
+        ContextStatus::Failed | ContextStatus::TimedOut | ContextStatus::Interrupted => Color::Red,
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let total_seconds = Duration::from_secs_f64(secs.max(0.0)).as_secs();
+    let hours = total_seconds / 3600;
+    let minutes = (total_seconds % 3600) / 60;
+    let seconds = total_seconds % 60;
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+fn format_ago(secs: f64) -> String {
+    let secs = Duration::from_secs_f64(secs.max(0.0)).as_secs();
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
@@ -7,6 +7,7 @@ use serde_json::to_string_pretty;
 use crate::{
     config::{RepoConfig, Selector},
     error::{RtError, RtResult},
+    shard::{Shard, ShardBy, select_shard},
     ui,
     venv::{ExecutionContext, RiotVenv, select_execution_contexts, venv_path},
 };
@@ -160,10 +161,14 @@ pub fn run(
     venvs: IndexMap<String, RiotVenv>,
     repo: &RepoConfig,
     selector: Selector,
+    shard: Option<(Shard, ShardBy)>,
     hash_only: bool,
     json: bool,
 ) -> RtResult<()> {
     let mut venvs = select_execution_contexts(venvs, selector)?;
+    if let Some((shard, by)) = shard {
+        venvs = select_shard(venvs, &repo.riot_root, shard, by)?;
+    }
 
     venvs.retain(|v| !v.execution_contexts.is_empty());
 
@@ -0,0 +1,153 @@
+use std::{fs, io::Write, sync::Arc};
+
+use indexmap::IndexMap;
+use rayon::current_num_threads;
+use tempfile::Builder;
+
+use crate::{
+    command::ManagedCommand,
+    commands::build::format_requirements,
+    config::{InterpolationVars, RepoConfig, Selector, scoped_env},
+    constants::REQUIREMENTS_DIR,
+    error::{RtError, RtResult},
+    progress::{
+        StepContext, StepId, StepOutcome, Task, TaskRunner, default_sink, summarize_errors,
+    },
+    ui,
+    venv::{RiotVenv, lockfile_path, lockfile_problems, select_execution_contexts},
+};
+
+/// Compile the requirements lockfile of every selected venv with `uv pip compile`.
+///
+/// Existing lockfiles that still satisfy the riotfile constraints are reported as cached unless
+/// `force` is set. With `check`, nothing is resolved and drifted lockfiles are reported instead.
+///
+/// # Errors
+///
+/// Returns an error when context selection fails, any venv cannot be resolved, or a lockfile
+/// fails the check.
+pub fn run(
+    venvs: IndexMap<String, RiotVenv>,
+    repo: &RepoConfig,
+    selector: Selector,
+    force: bool,
+    check: bool,
+) -> RtResult<()> {
+    let mut selected = select_execution_contexts(venvs, selector)?;
+    selected.retain(|venv| !venv.execution_contexts.is_empty());
+
+    if check {
+        return check_lockfiles(repo, &selected);
+    }
+
+    let requirements_dir = repo.riot_root.join(REQUIREMENTS_DIR);
+    fs::create_dir_all(&requirements_dir).map_err(|err| {
+        RtError::message(format!(
+            "error: could not create requirements directory {}: {err}",
+            requirements_dir.display()
+        ))
+    })?;
+
+    let sink = default_sink(repo.events);
+    let runner = TaskRunner::new(Arc::clone(&sink)).with_parallelism(Some(current_num_threads()));
+
+    let tasks: Vec<Task<'_, RtError>> = selected
+        .iter()
+        .map(|venv| {
+            let step_id = format!("lock {}", venv.hash);
+            let label = format!("{step_id} {} ({})", venv.name, venv.python);
+            Task::new(StepId::new(step_id), label, move |ctx| {
+                compile_lockfile(repo, venv, force, &ctx)
+            })
+        })
+        .collect();
+
+    let errors = runner.run(tasks).map_err(|err| {
+        RtError::message(format!(
+            "error: could not configure lock parallelism ({err})"
+        ))
+    })?;
+
+    if summarize_errors(&errors, "lock") {
+        return Err(RtError::silent(1));
+    }
+
+    Ok(())
+}
+
+fn compile_lockfile(
+    repo: &RepoConfig,
+    venv: &RiotVenv,
+    force: bool,
+    ctx: &StepContext,
+) -> RtResult<StepOutcome> {
+    let output_path = lockfile_path(&repo.riot_root, &venv.hash);
+    if !force
+        && output_path.is_file()
+        && lockfile_problems(&venv.pkgs, &venv.resolved_pkgs).is_empty()
+    {
+        return Ok(StepOutcome::Cached);
+    }
+
+    let mut input = Builder::new().suffix(".in").tempfile()?;
+    input.write_all(format_requirements(&venv.pkgs).as_bytes())?;
+    input.flush()?;
+
+    let status = ManagedCommand::new_uv("pip", Arc::clone(&ctx.sink), ctx.step_id.clone())
+        .offline(repo.offline)
+        .envs(
+            InterpolationVars::for_repo(repo)
+                .python(&venv.python)
+                .venv(&venv.name, &venv.hash)
+                .expand_env(&scoped_env(&repo.build_env, &venv.build_env)),
+        )
+        .arg("compile")
+        .index(&repo.index)
+        .arg("--no-annotate")
+        .arg("--python")
+        .arg(&venv.python)
+        .arg("--output-file")
+        .arg(&output_path)
+        .arg(input.path())
+        .status()?;
+
+    if !status.success() {
+        return Err(RtError::message(format!(
+            "error: uv pip compile failed with status {status}"
+        )));
+    }
+
+    Ok(StepOutcome::Done)
+}
+
+/// Report every selected venv whose lockfile is missing or no longer satisfies its constraints.
+fn check_lockfiles(repo: &RepoConfig, selected: &[RiotVenv]) -> RtResult<()> {
+    ui::step(format!("Checking {} lockfile(s)", selected.len()));
+
+    let mut problem_count = 0usize;
+    for venv in selected {
+        let venv_label = format!("{} {} ({})", venv.hash, venv.name, venv.python);
+        let path = lockfile_path(&repo.riot_root, &venv.hash);
+        if !path.is_file() {
+            ui::detail(format!("{venv_label}: missing lockfile {}", path.display()));
+            problem_count += 1;
+            continue;
+        }
+
+        for problem in lockfile_problems(&venv.pkgs, &venv.resolved_pkgs) {
+            ui::detail(format!("{venv_label}: {}", problem.detail));
+            problem_count += 1;
+        }
+    }
+
+    ui::blank_line();
+
+    if problem_count == 0 {
+        return Ok(());
+    }
+
+    let plural = if problem_count == 1 { "" } else { "s" };
+    Err(RtError::message(format!(
+        "error: {problem_count} lockfile problem{plural} found, run `rt lock` to refresh them"
+    )))
+}
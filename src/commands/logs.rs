@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    thread,
+    time::Duration,
+};
+
+use indexmap::IndexMap;
+
+use crate::{
+    commands::build::{context_step, deps_install_step, deps_key, dev_install_step},
+    config::{RepoConfig, Selector},
+    error::{RtError, RtResult},
+    interrupt,
+    step_log::{self, FINISHED_MARKER, LogPhase},
+    ui,
+    venv::{RiotVenv, select_execution_contexts},
+};
+
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Print the latest log of the build or run steps of the execution context or venv `hash`, or
+/// that of attempt `attempt` of its latest run, optionally following it until its step finishes.
+///
+/// # Errors
+///
+/// Returns an error if no log was recorded or it cannot be read.
+pub fn run(
+    venvs: IndexMap<String, RiotVenv>,
+    repo: &RepoConfig,
+    hash: &str,
+    phase: Option<LogPhase>,
+    attempt: Option<u32>,
+    follow: bool,
+) -> RtResult<()> {
+    let selected = select_execution_contexts(venvs, Selector::Pattern(hash.to_string()))?;
+    let steps: Vec<(LogPhase, String)> = log_steps(&selected, hash)
+        .into_iter()
+        .filter(|(step_phase, _)| phase.is_none_or(|phase| phase == *step_phase))
+        .collect();
+    let Some(path) = step_log::latest(&repo.riot_root, &steps) else {
+        return Err(RtError::message(format!(
+            "error: no logs recorded for {hash}"
+        )));
+    };
+    let mut path = match attempt {
+        Some(attempt) => step_log::attempt_log(&path, attempt).ok_or_else(|| {
+            RtError::message(format!(
+                "error: no log recorded for attempt {attempt} of the latest run of {hash}"
+            ))
+        })?,
+        None => path,
+    };
+    ui::step(format!("Log {}", path.display()));
+
+    let mut file = File::open(&path)?;
+    let mut stdout = io::stdout().lock();
+    if !follow {
+        io::copy(&mut file, &mut stdout)?;
+        return Ok(());
+    }
+
+    interrupt::handle_signals();
+    let mut line = Vec::new();
+    let mut chunk = Vec::new();
+    loop {
+        chunk.clear();
+        file.read_to_end(&mut chunk)?;
+        stdout.write_all(&chunk)?;
+        stdout.flush()?;
+        let mut finished = false;
+        for &byte in &chunk {
+            if byte == b'\n' {
+                finished |= line.starts_with(FINISHED_MARKER.as_bytes());
+                line.clear();
+            } else {
+                line.push(byte);
+            }
+        }
+        if finished && let Some(next) = step_log::next_attempt_log(&path) {
+            ui::step(format!("Log {}", next.display()));
+            file = File::open(&next)?;
+            path = next;
+            continue;
+        }
+        if finished || interrupt::requested() {
+            return Ok(());
+        }
+        thread::sleep(FOLLOW_POLL_INTERVAL);
+    }
+}
+
+/// Steps whose logs concern `hash`: running its execution contexts and building them, their
+/// dependencies and the dev install they use. A hash the riotfile no longer defines still finds
+/// the logs of an execution context with that hash.
+fn log_steps(selected: &[RiotVenv], hash: &str) -> Vec<(LogPhase, String)> {
+    if selected.is_empty() {
+        return vec![
+            (LogPhase::Run, hash.to_string()),
+            (LogPhase::Build, context_step(hash)),
+        ];
+    }
+
+    let mut steps = Vec::new();
+    for venv in selected {
+        for ctx in &venv.execution_contexts {
+            steps.push((LogPhase::Run, ctx.hash.clone()));
+            steps.push((LogPhase::Build, context_step(&ctx.hash)));
+        }
+        steps.push((LogPhase::Build, deps_install_step(&deps_key(venv))));
+        if venv
+            .execution_contexts
+            .iter()
+            .any(|ctx| !ctx.skip_dev_install)
+        {
+            steps.push((LogPhase::Build, dev_install_step(&venv.python)));
+        }
+    }
+    steps
+}
@@ -1,8 +1,15 @@
 pub mod activate;
+pub mod affected;
 pub mod build;
+pub mod cache;
 pub mod clean;
+pub mod config;
 pub mod describe;
+pub mod gc;
+pub mod history;
 pub mod list;
+pub mod lock;
+pub mod logs;
 pub mod run;
 pub mod shell;
 pub mod switch;
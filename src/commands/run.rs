@@ -1,18 +1,43 @@
-use std::{io::IsTerminal, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::IsTerminal,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
 use indexmap::IndexMap;
 
 use crate::{
     command::ManagedCommand,
-    commands::build::{build_selected_contexts, collect_context_indices},
-    config::{RepoConfig, RunConfig, Selector},
-    error::{RtError, RtResult},
+    commands::{
+        affected::select_affected,
+        build::{build_selected_contexts, collect_context_indices},
+    },
+    config::{InterpolationVars, RepoConfig, RunConfig, Selector, format_duration, scoped_env},
+    error::{RtError, RtResult, Termination},
+    history::{self, ContextResult, ContextStatus, RunRecord},
+    interrupt, junit,
     progress::{
         MultiplexedProgressLogger, PlainProgressLogger, ProgressLogger, StepContext, StepId,
-        StepOutcome, Task, TaskRunner, summarize_errors,
+        StepOutcome, Task, TaskRunner, event_sink, summarize_errors,
+    },
+    riot_root::{context_builds, mark_in_use},
+    shard::select_shard,
+    step_log::{self, LogPhase},
+    ui,
+    venv::{
+        ExecutionContext, RiotVenv, is_pytest_command, select_execution_contexts, venv_python_path,
     },
-    venv::{ExecutionContext, RiotVenv, select_execution_contexts, venv_python_path},
 };
+
+/// Exit code recorded for contexts stopped after their timeout, as with coreutils `timeout`.
+const TIMED_OUT_EXIT_CODE: u8 = 124;
+
+/// Exit code recorded for contexts stopped or skipped because rt was interrupted, as for SIGINT.
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
 /// Build and execute the command for the given execution context.
 ///
 /// # Errors
@@ -27,7 +52,22 @@ pub fn run(
     parallel: Option<usize>,
     run_config: &RunConfig,
 ) -> RtResult<()> {
-    let selected = select_execution_contexts(venvs, selector)?;
+    let mut selected = select_execution_contexts(venvs, selector)?;
+    if let Some(base) = &run_config.affected_base {
+        selected = select_affected(selected, repo, base)?;
+    }
+    if let Some((shard, by)) = run_config.shard {
+        selected = select_shard(selected, &repo.riot_root, shard, by)?;
+    }
+    let run_config = if run_config.rerun_failed {
+        let Some(run_config) = select_failed(&mut selected, repo, run_config)? else {
+            return Ok(());
+        };
+        Cow::Owned(run_config)
+    } else {
+        Cow::Borrowed(run_config)
+    };
+    let run_config = run_config.as_ref();
 
     for selected_venv in &selected {
         for exc_ctx in &selected_venv.execution_contexts {
@@ -40,10 +80,17 @@ pub fn run(
         }
     }
 
-    build_selected_contexts(repo, &selected, force_reinstall, no_editable)?;
+    build_selected_contexts(
+        repo,
+        &selected,
+        force_reinstall,
+        no_editable,
+        run_config.fail_fast,
+    )?;
 
-    let sink: Arc<dyn ProgressLogger> = match parallel {
-        Some(n) if n > 0 && std::io::stderr().is_terminal() => {
+    let sink: Arc<dyn ProgressLogger> = match (repo.events, parallel) {
+        (Some(format), _) => event_sink(format),
+        (None, Some(n)) if n > 0 && std::io::stderr().is_terminal() => {
             match MultiplexedProgressLogger::new() {
                 Ok(logger) => Arc::new(logger),
                 Err(_) => Arc::new(PlainProgressLogger::default()),
@@ -52,9 +99,53 @@ pub fn run(
         _ => Arc::new(PlainProgressLogger::default()),
     };
 
+    let sink = step_log::record(sink, &repo.riot_root, LogPhase::Run);
     run_contexts(repo, &selected, run_config, parallel, sink)
 }
 
+/// Narrow `selected` to the contexts that failed in the previous run. Unless a command or
+/// arguments are given, they are reused from that run. Returns `None` when nothing failed.
+fn select_failed(
+    selected: &mut Vec<RiotVenv>,
+    repo: &RepoConfig,
+    run_config: &RunConfig,
+) -> RtResult<Option<RunConfig>> {
+    let Some(last) = history::load(&repo.riot_root)?.pop() else {
+        return Err(RtError::message(
+            "error: no run history found; nothing to re-run with --failed",
+        ));
+    };
+    let failed: HashSet<&str> = last.failed().map(|result| result.hash.as_str()).collect();
+    if failed.is_empty() {
+        ui::step("No execution context failed in the previous run");
+        return Ok(None);
+    }
+
+    for venv in selected.iter_mut() {
+        venv.execution_contexts
+            .retain(|ctx| failed.contains(ctx.hash.as_str()));
+    }
+    selected.retain(|venv| !venv.execution_contexts.is_empty());
+    let count: usize = selected
+        .iter()
+        .map(|venv| venv.execution_contexts.len())
+        .sum();
+    ui::step(format!("Re-running {count} failed execution context(s)"));
+
+    let mut run_config = run_config.clone();
+    if run_config.command_override.is_none() && run_config.cmdargs.is_empty() {
+        run_config.command_override = last.command_override;
+        run_config.cmdargs = last.cmdargs;
+        if let Some(command) = &run_config.command_override {
+            ui::detail(format!("command: {command}"));
+        }
+        if !run_config.cmdargs.is_empty() {
+            ui::detail(format!("cmdargs: {}", format_cmdargs(&run_config.cmdargs)));
+        }
+    }
+    Ok(Some(run_config))
+}
+
 fn run_contexts(
     repo: &RepoConfig,
     selected: &[RiotVenv],
@@ -62,15 +153,60 @@ fn run_contexts(
     parallelism: Option<usize>,
     sink: Arc<dyn ProgressLogger>,
 ) -> RtResult<()> {
-    let runner = TaskRunner::new(sink).with_parallelism(parallelism);
+    let runner = TaskRunner::new(sink)
+        .with_parallelism(parallelism)
+        .with_fail_fast(run_config.fail_fast);
+    let indices = collect_context_indices(selected);
+    let results: Mutex<Vec<Option<ContextResult>>> = Mutex::new(vec![None; indices.len()]);
+    let junit_dir = run_config
+        .junit_xml
+        .as_ref()
+        .map(|_| tempfile::tempdir())
+        .transpose()?;
+    let run_started_at = SystemTime::now();
+    let run_timer = Instant::now();
 
-    let tasks: Vec<Task<'_, RtError>> = collect_context_indices(selected)
+    let tasks: Vec<Task<'_, RtError>> = indices
         .iter()
-        .map(|&(venv_i, exc_i)| {
-            let exc_ctx: ExecutionContext = selected[venv_i].execution_contexts[exc_i].clone();
+        .enumerate()
+        .map(|(slot, &(venv_i, exc_i))| {
+            let venv = &selected[venv_i];
+            let exc_ctx: ExecutionContext = venv.execution_contexts[exc_i].clone();
             let label = format!("{} {}", run_config.action_label, exc_ctx.hash);
+            let results = &results;
+            let junit_report = junit_dir
+                .as_ref()
+                .map(|dir| junit::report_path(dir.path(), &exc_ctx.hash));
             Task::new(StepId::new(exc_ctx.hash.clone()), label, move |ctx| {
-                execute_command(repo, &exc_ctx, run_config, &ctx)
+                let command_line = command_line(&exc_ctx, run_config, junit_report.as_deref());
+                let started_at = SystemTime::now();
+                let timer = Instant::now();
+                let (outcome, attempts) = execute_with_retries(
+                    repo,
+                    venv,
+                    &exc_ctx,
+                    &command_line,
+                    junit_report.as_deref(),
+                    run_config,
+                    &ctx,
+                );
+                let (status, exit_code, skip_reason) = context_status(&outcome);
+                let result = ContextResult {
+                    hash: exc_ctx.hash.clone(),
+                    name: venv.name.clone(),
+                    python: venv.python.clone(),
+                    command: command_line,
+                    status,
+                    exit_code,
+                    started_at: history::epoch_secs(started_at),
+                    duration_secs: timer.elapsed().as_secs_f64(),
+                    attempts,
+                    skip_reason,
+                };
+                results
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)[slot] = Some(result);
+                outcome
             })
         })
         .collect();
@@ -79,6 +215,44 @@ fn run_contexts(
         RtError::message(format!("error: could not configure parallelism ({err})"))
     })?;
 
+    let results = results
+        .into_inner()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .into_iter()
+        .zip(&indices)
+        .map(|(result, &(venv_i, exc_i))| {
+            result.unwrap_or_else(|| {
+                let venv = &selected[venv_i];
+                let exc_ctx = &venv.execution_contexts[exc_i];
+                let junit_report = junit_dir
+                    .as_ref()
+                    .map(|dir| junit::report_path(dir.path(), &exc_ctx.hash));
+                let command = command_line(exc_ctx, run_config, junit_report.as_deref());
+                let label = format!("{} {}", run_config.action_label, exc_ctx.hash);
+                let reason = errors
+                    .iter()
+                    .find(|(error_label, _)| *error_label == label)
+                    .map(|(_, err)| err.to_string());
+                not_run_result(venv, exc_ctx, command, reason, run_started_at)
+            })
+        })
+        .collect();
+    let record = RunRecord {
+        started_at: history::epoch_secs(run_started_at),
+        duration_secs: run_timer.elapsed().as_secs_f64(),
+        command_override: run_config.command_override.clone(),
+        cmdargs: run_config.cmdargs.clone(),
+        results,
+    };
+    if let Err(err) = history::append(&repo.riot_root, &record) {
+        eprintln!("warning: could not record run history: {err}");
+    }
+    if let (Some(output), Some(dir)) = (&run_config.junit_xml, &junit_dir) {
+        junit::merge(&record.results, dir.path(), output)?;
+        ui::step(format!("JUnit report written to {}", output.display()));
+    }
+
+    summarize_flaky(&record.results);
     if summarize_errors(&errors, "run") {
         return Err(RtError::silent(1));
     }
@@ -86,39 +260,182 @@ fn run_contexts(
     Ok(())
 }
 
-fn execute_command(
+/// Status, exit code and skip reason recorded for a context that ended with `outcome`.
+fn context_status(outcome: &RtResult<StepOutcome>) -> (ContextStatus, u8, Option<String>) {
+    match outcome {
+        Ok(StepOutcome::Flaky) => (ContextStatus::Flaky, 0, None),
+        Ok(_) => (ContextStatus::Passed, 0, None),
+        Err(err) => match err.termination() {
+            Some(Termination::TimedOut) => (ContextStatus::TimedOut, err.exit_code(), None),
+            Some(Termination::Interrupted) => (ContextStatus::Interrupted, err.exit_code(), None),
+            Some(Termination::Skipped) => (
+                ContextStatus::Skipped,
+                err.exit_code(),
+                Some(err.to_string()),
+            ),
+            None => (ContextStatus::Failed, err.exit_code(), None),
+        },
+    }
+}
+
+/// Result of a context that never started, because rt was interrupted or an earlier context
+/// failed in fail-fast mode. `reason` is the error the runner recorded for it.
+fn not_run_result(
+    venv: &RiotVenv,
+    exc_ctx: &ExecutionContext,
+    command: String,
+    reason: Option<String>,
+    run_started_at: SystemTime,
+) -> ContextResult {
+    let (status, exit_code, skip_reason) = if interrupt::requested() {
+        (ContextStatus::Interrupted, INTERRUPTED_EXIT_CODE, None)
+    } else {
+        (ContextStatus::Skipped, 0, reason)
+    };
+    ContextResult {
+        hash: exc_ctx.hash.clone(),
+        name: venv.name.clone(),
+        python: venv.python.clone(),
+        command,
+        status,
+        exit_code,
+        started_at: history::epoch_secs(run_started_at),
+        duration_secs: 0.0,
+        attempts: 0,
+        skip_reason,
+    }
+}
+
+/// List the contexts that passed only after a retry.
+fn summarize_flaky(results: &[ContextResult]) {
+    let flaky: Vec<&ContextResult> = results
+        .iter()
+        .filter(|result| result.status == ContextStatus::Flaky)
+        .collect();
+    if flaky.is_empty() {
+        return;
+    }
+    let count = flaky.len();
+    let plural = if count == 1 { "" } else { "s" };
+    eprintln!("flaky summary: {count} execution context{plural} passed only after a retry");
+    for (idx, result) in flaky.iter().enumerate() {
+        let num = idx + 1;
+        eprintln!(
+            "  {num}. {} ({} py{}): passed on attempt {}",
+            result.hash, result.name, result.python, result.attempts
+        );
+    }
+}
+
+/// `[env.run]` of rt.toml, overlaid with the `[[venv]]` sections matching `venv` and with
+/// `${VAR}` references expanded for an execution context.
+#[must_use]
+pub fn run_env(
     repo: &RepoConfig,
+    venv: &RiotVenv,
+    exc_ctx: &ExecutionContext,
+) -> HashMap<String, String> {
+    InterpolationVars::for_repo(repo)
+        .python(&venv.python)
+        .venv(&venv.name, &exc_ctx.hash)
+        .expand_env(&scoped_env(&repo.run_env, &venv.run_env))
+}
+
+/// The shell command of an execution context with `{cmdargs}` substituted. Pytest commands also
+/// get `--junitxml` when a `JUnit` report is requested.
+fn command_line(
     exc_ctx: &ExecutionContext,
     run_config: &RunConfig,
+    junit_report: Option<&Path>,
+) -> String {
+    let mut command_template = run_config.command_override.as_ref().map_or_else(
+        || exc_ctx.command.as_ref().unwrap().clone(),
+        std::clone::Clone::clone,
+    );
+    if !command_template.contains("{cmdargs}") {
+        command_template.push_str(" {cmdargs}");
+    }
+    let mut cmdargs = run_config.cmdargs.clone();
+    if let Some(report) = junit_report.filter(|_| is_pytest_command(&command_template)) {
+        cmdargs.insert(0, format!("--junitxml={}", report.display()));
+    }
+    command_template.replace("{cmdargs}", &format_cmdargs(&cmdargs))
+}
+
+/// Execute the command of a context, running it again after a failure as many times as the
+/// configured retries allow. Returns the outcome, flaky if a retry passed, and the attempts made.
+///
+/// The `JUnit` report of an earlier attempt is removed before each attempt, so that only one
+/// written by the last attempt is merged.
+fn execute_with_retries(
+    repo: &RepoConfig,
+    venv: &RiotVenv,
+    exc_ctx: &ExecutionContext,
+    command_line: &str,
+    junit_report: Option<&Path>,
+    run_config: &RunConfig,
     ctx: &StepContext,
-) -> RtResult<StepOutcome> {
-    let command_line = {
-        let exc_ctx: &ExecutionContext = exc_ctx;
-        let mut command_template = run_config.command_override.as_ref().map_or_else(
-            || exc_ctx.command.as_ref().unwrap().clone(),
-            std::clone::Clone::clone,
-        );
-        if !command_template.contains("{cmdargs}") {
-            command_template.push_str(" {cmdargs}");
+) -> (RtResult<StepOutcome>, u32) {
+    let timeout = run_config.timeout.or(venv.timeout).or(repo.timeout);
+    let retries = run_config.retries.or(venv.retries).or(repo.retries);
+    let attempts = retries.unwrap_or(0).saturating_add(1);
+    let mut attempt = 1;
+    loop {
+        if let Some(report) = junit_report
+            && let Err(err) = junit::clear_report(report)
+        {
+            return (Err(err.into()), attempt);
         }
-        command_template.replace("{cmdargs}", &format_cmdargs(&run_config.cmdargs))
-    };
+        match execute_command(repo, venv, exc_ctx, command_line, timeout, ctx) {
+            Ok(_) if attempt > 1 => return (Ok(StepOutcome::Flaky), attempt),
+            Err(err)
+                if attempt < attempts
+                    && err.termination() != Some(Termination::Interrupted)
+                    && !ctx.cancel.is_cancelled() =>
+            {
+                attempt += 1;
+                ctx.sink.retry(&ctx.step_id, attempt, attempts);
+            }
+            outcome => return (outcome, attempt),
+        }
+    }
+}
 
+fn execute_command(
+    repo: &RepoConfig,
+    venv: &RiotVenv,
+    exc_ctx: &ExecutionContext,
+    command_line: &str,
+    timeout: Option<Duration>,
+    ctx: &StepContext,
+) -> RtResult<StepOutcome> {
+    let _in_use = mark_in_use(&context_builds(&repo.riot_root, venv, exc_ctx));
     let status = ManagedCommand::new_uv("run", Arc::clone(&ctx.sink), ctx.step_id.clone())
+        .offline(repo.offline)
         .envs(&exc_ctx.env)
-        .envs(repo.run_env.as_ref())
+        .envs(run_env(repo, venv, exc_ctx))
         .arg("--no-project")
         .args([
             "--python",
             &venv_python_path(&repo.riot_root, &exc_ctx.hash),
         ])
         .arg("--")
-        .args(["sh", "-c", &command_line])
+        .args(["sh", "-c", command_line])
+        .timeout(timeout)
         .status()
-        .map_err(|err| {
-            RtError::message(format!(
+        .map_err(|err| match (err.kind(), timeout) {
+            (std::io::ErrorKind::TimedOut, Some(timeout)) => RtError::with_code(
+                TIMED_OUT_EXIT_CODE,
+                format!("timed out after {}", format_duration(timeout)),
+            )
+            .terminated(Termination::TimedOut),
+            (std::io::ErrorKind::Interrupted, _) => {
+                RtError::with_code(INTERRUPTED_EXIT_CODE, "interrupted")
+                    .terminated(Termination::Interrupted)
+            }
+            _ => RtError::message(format!(
                 "error: failed to execute command `{command_line}`: {err}"
-            ))
+            )),
         })?;
 
     status
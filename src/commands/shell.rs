@@ -7,9 +7,10 @@ use std::{
 use indexmap::IndexMap;
 
 use crate::{
-    commands::build::build_selected_contexts,
+    commands::{build::build_selected_contexts, run::run_env},
     config::{RepoConfig, Selector},
     error::{RtError, RtResult},
+    riot_root::{context_builds, mark_in_use},
     ui::{self},
     venv::{ExecutionContext, RiotVenv, select_execution_contexts, venv_python_path},
 };
@@ -27,11 +28,17 @@ pub fn run(
 ) -> RtResult<()> {
     let target = resolve_target(venvs, hash)?;
 
-    build_selected_contexts(repo, std::slice::from_ref(&target), force_reinstall, false)?;
+    build_selected_contexts(
+        repo,
+        std::slice::from_ref(&target),
+        force_reinstall,
+        false,
+        None,
+    )?;
     let ctx = &target.execution_contexts[0];
     ui::step(format!("Spawning shell for execution context {}", ctx.hash));
 
-    launch_shell(repo, ctx)?;
+    launch_shell(repo, &target, ctx)?;
 
     Ok(())
 }
@@ -83,7 +90,7 @@ pub fn make_venv_shell_context(venv: &RiotVenv) -> ExecutionContext {
     }
 }
 
-fn launch_shell(repo: &RepoConfig, exc_ctx: &ExecutionContext) -> RtResult<()> {
+fn launch_shell(repo: &RepoConfig, venv: &RiotVenv, exc_ctx: &ExecutionContext) -> RtResult<()> {
     let python_path = venv_python_path(&repo.riot_root, &exc_ctx.hash);
 
     let shell = preferred_shell();
@@ -114,8 +121,9 @@ fn launch_shell(repo: &RepoConfig, exc_ctx: &ExecutionContext) -> RtResult<()> {
         command.env(key, value);
     }
 
-    command.envs(repo.run_env.iter());
+    command.envs(run_env(repo, venv, exc_ctx));
 
+    let _in_use = mark_in_use(&context_builds(&repo.riot_root, venv, exc_ctx));
     let status = command.status().map_err(|err| {
         RtError::message(format!(
             "error: failed to spawn shell for {}: {err}",
@@ -24,7 +24,13 @@ pub fn run(
     let target = resolve_target(venvs, hash)?;
     let ctx_hash = &target.execution_contexts[0].hash;
 
-    build_selected_contexts(repo, std::slice::from_ref(&target), force_reinstall, false)?;
+    build_selected_contexts(
+        repo,
+        std::slice::from_ref(&target),
+        force_reinstall,
+        false,
+        None,
+    )?;
 
     let project_root = repo
         .riotfile_path
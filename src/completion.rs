@@ -2,11 +2,12 @@ use std::collections::{HashMap, HashSet};
 
 use clap::builder::StyledStr;
 use clap_complete::{CompletionCandidate, engine::ValueCompleter};
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 
 use crate::{
     config::Selector,
     load_context_with_default_provider, locate_riotfile,
+    query::is_query,
     ui::{format_envs, format_pkgs},
     venv::{RiotVenv, compare_python_versions, select_execution_contexts},
 };
@@ -44,29 +45,80 @@ impl ValueCompleter for PythonCompleter {
     }
 }
 
-pub struct NameCompleter;
-
-impl ValueCompleter for NameCompleter {
-    fn complete(&self, current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
-        let Some(current) = current.to_str() else {
-            return vec![];
-        };
-
-        let selected_venvs =
-            select_execution_contexts(get_venvs(), Selector::Pattern(current.to_string()))
-                .unwrap_or_default();
-
-        let mut venv_names = HashSet::new();
-
-        for selected in selected_venvs {
-            venv_names.insert(selected.name);
+/// Complete the last term of a selector expression: field names, then package and variable
+/// names, then values seen in the riotfile.
+fn complete_query(hint: &str, venvs: &IndexMap<String, RiotVenv>) -> Vec<CompletionCandidate> {
+    let chars: Vec<(usize, char)> = hint.char_indices().collect();
+    let term_start = chars
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(idx, (_, c))| {
+            matches!(c, '&' | '|' | '(')
+                || (*c == '!' && chars.get(idx + 1).is_none_or(|(_, next)| *next != '='))
+        })
+        .map_or(0, |(_, (pos, c))| pos + c.len_utf8());
+    let (head, term) = hint.split_at(term_start);
+    let head = format!("{head}{}", &term[..term.len() - term.trim_start().len()]);
+    let term = term.trim_start();
+
+    let mut values: IndexSet<String> = IndexSet::new();
+    let prefix;
+    if let Some(op_start) = term.find(['~', '=', '!', '<', '>', '^']) {
+        let value_start = op_start
+            + term[op_start..]
+                .find(|c: char| !matches!(c, '~' | '=' | '!' | '<' | '>' | '^'))
+                .unwrap_or(term.len() - op_start);
+        prefix = &term[..value_start];
+        let field = term[..op_start].trim();
+        for venv in venvs.values() {
+            match field.split_once(':') {
+                None if field == "name" => {
+                    values.insert(venv.name.clone());
+                }
+                None if field == "python" => {
+                    values.insert(venv.python.clone());
+                }
+                Some(("env", name)) => values.extend(
+                    venv.execution_contexts
+                        .iter()
+                        .filter_map(|ctx| ctx.env.get(name).cloned()),
+                ),
+                _ => {}
+            }
         }
-
-        let mut venv_names: Vec<_> = venv_names.iter().collect();
-        venv_names.sort();
-
-        venv_names.iter().map(CompletionCandidate::new).collect()
+    } else if let Some((field, _)) = term.split_once(':') {
+        prefix = &term[..=field.len()];
+        for venv in venvs.values() {
+            match field {
+                "pkg" => values.extend(venv.pkgs.keys().cloned()),
+                "env" => values.extend(
+                    venv.execution_contexts
+                        .iter()
+                        .flat_map(|ctx| ctx.env.keys().cloned()),
+                ),
+                _ => {}
+            }
+        }
+    } else {
+        prefix = "";
+        values.extend(
+            ["name~", "python>=", "hash=", "pkg:", "env:"]
+                .into_iter()
+                .map(str::to_string),
+        );
     }
+
+    let partial = &term[prefix.len()..];
+    let mut values: Vec<String> = values
+        .into_iter()
+        .filter(|value| value.starts_with(partial))
+        .collect();
+    values.sort();
+    values
+        .into_iter()
+        .map(|value| CompletionCandidate::new(format!("{head}{prefix}{value}")))
+        .collect()
 }
 
 fn complete_selector(current: &std::ffi::OsStr, with_names: bool) -> Vec<CompletionCandidate> {
@@ -74,6 +126,10 @@ fn complete_selector(current: &std::ffi::OsStr, with_names: bool) -> Vec<Complet
         return vec![];
     };
 
+    if with_names && is_query(hint) {
+        return complete_query(hint, &get_venvs());
+    }
+
     let selected = select_execution_contexts(get_venvs(), Selector::Pattern(hint.to_string()))
         .unwrap_or_default();
 
@@ -90,7 +146,7 @@ fn complete_selector(current: &std::ffi::OsStr, with_names: bool) -> Vec<Complet
         let pkgs_detail = if selected.pkgs.is_empty() {
             String::new()
         } else {
-            format!(" {}", format_pkgs(&selected.pkgs, &selected.shared_pkgs),)
+            format!(" {}", format_pkgs(&selected.pkgs, &selected.shared_pkgs))
         };
 
         let short_hash = selected.hash.clone();
@@ -105,7 +161,7 @@ fn complete_selector(current: &std::ffi::OsStr, with_names: bool) -> Vec<Complet
             let env_detail = if selected.shared_env.is_empty() {
                 String::new()
             } else {
-                format!(" {}", format_envs(&ctx.env, &selected.shared_env),)
+                format!(" {}", format_envs(&ctx.env, &selected.shared_env))
             };
             let ctx_hash = ctx.hash.clone();
             let ctx_candidate =
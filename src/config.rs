@@ -1,17 +1,452 @@
 use std::{
     collections::HashMap,
+    fmt::Write as _,
     fs,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-use crate::error::{RtError, RtResult};
+use fancy_regex::Regex;
+use indexmap::IndexMap;
+
+use crate::{
+    error::{RtError, RtResult},
+    progress::{EventFormat, FailFast},
+    shard::{Shard, ShardBy},
+    venv::RiotVenv,
+};
 
 pub struct RepoConfig {
     pub riotfile_path: PathBuf,
     pub riot_root: PathBuf,
     pub build_env: Arc<HashMap<String, String>>,
     pub run_env: Arc<HashMap<String, String>>,
+    /// Run every uv invocation with `--offline`.
+    pub offline: bool,
+    pub index: Arc<IndexConfig>,
+    /// Worker count used by `rt run` when `--parallel` is not given.
+    pub parallel: Option<usize>,
+    /// Python versions selected when `--python` is not given.
+    pub python: Option<Vec<String>>,
+    /// Per-context timeout of `rt run` when neither `--timeout` nor a `[[venv]]` section sets one.
+    pub timeout: Option<Duration>,
+    /// Retries of a failed context when neither `--retries` nor a `[[venv]]` section sets them.
+    pub retries: Option<u32>,
+    /// Machine-readable progress events requested with `--events`.
+    pub events: Option<EventFormat>,
+}
+
+/// Variables available to `${VAR}` and `${VAR:-default}` interpolation in `rt.toml` env tables.
+///
+/// rt-provided variables take precedence over the process environment; unset variables expand
+/// to an empty string.
+#[derive(Debug, Clone, Default)]
+pub struct InterpolationVars {
+    vars: Vec<(&'static str, String)>,
+}
+
+impl InterpolationVars {
+    /// Variables known for the whole repository: `RT_PROJECT_ROOT` and `RT_RIOT_ROOT`.
+    #[must_use]
+    pub fn for_repo(repo: &RepoConfig) -> Self {
+        let project_root = repo
+            .riotfile_path
+            .parent()
+            .map(|parent| parent.display().to_string())
+            .unwrap_or_default();
+        Self::default()
+            .with("RT_PROJECT_ROOT", project_root)
+            .with("RT_RIOT_ROOT", repo.riot_root.display().to_string())
+    }
+
+    /// Add `RT_PYTHON`.
+    #[must_use]
+    pub fn python(self, python: &str) -> Self {
+        self.with("RT_PYTHON", python)
+    }
+
+    /// Add `RT_VENV_NAME` and `RT_VENV_HASH` (the execution context hash when building or
+    /// running a context).
+    #[must_use]
+    pub fn venv(self, name: &str, hash: &str) -> Self {
+        self.with("RT_VENV_NAME", name).with("RT_VENV_HASH", hash)
+    }
+
+    fn with(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.vars.push((name, value.into()));
+        self
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.vars
+            .iter()
+            .find(|(var, _)| *var == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var(name).ok())
+    }
+
+    /// Expand every `${VAR}` and `${VAR:-default}` reference in `value`.
+    #[must_use]
+    pub fn expand(&self, value: &str) -> String {
+        let mut out = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(start) = rest.find("${") {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            out.push_str(&rest[..start]);
+            let reference = &rest[start + 2..start + len];
+            let (name, default) = reference
+                .split_once(":-")
+                .map_or((reference, None), |(name, default)| (name, Some(default)));
+            match self.lookup(name).filter(|value| !value.is_empty()) {
+                Some(value) => out.push_str(&value),
+                None => out.push_str(default.unwrap_or_default()),
+            }
+            rest = &rest[start + len + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Expand every value of an env table.
+    #[must_use]
+    pub fn expand_env(&self, env: &HashMap<String, String>) -> HashMap<String, String> {
+        env.iter()
+            .map(|(key, value)| (key.clone(), self.expand(value)))
+            .collect()
+    }
+}
+
+/// Prerelease policies accepted by uv's `--prerelease`.
+const PRERELEASE_POLICIES: &[&str] = &[
+    "disallow",
+    "allow",
+    "if-necessary",
+    "explicit",
+    "if-necessary-or-explicit",
+];
+
+/// Package index settings from the `[index]` section of `rt.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexConfig {
+    pub url: Option<String>,
+    pub extra_urls: Vec<String>,
+    pub find_links: Vec<String>,
+    pub no_binary: Vec<String>,
+    pub only_binary: Vec<String>,
+    pub prerelease: Option<String>,
+}
+
+impl IndexConfig {
+    /// Combine with the settings of a higher-precedence file, which replace these key by key.
+    #[must_use]
+    pub fn merge(self, over: Self) -> Self {
+        let pick = |base: Vec<String>, over: Vec<String>| if over.is_empty() { base } else { over };
+        Self {
+            url: over.url.or(self.url),
+            extra_urls: pick(self.extra_urls, over.extra_urls),
+            find_links: pick(self.find_links, over.find_links),
+            no_binary: pick(self.no_binary, over.no_binary),
+            only_binary: pick(self.only_binary, over.only_binary),
+            prerelease: over.prerelease.or(self.prerelease),
+        }
+    }
+
+    /// Arguments passing these settings to `uv pip install` or `uv pip compile`.
+    #[must_use]
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: &str| {
+            args.push(flag.to_string());
+            args.push(value.to_string());
+        };
+        if let Some(url) = &self.url {
+            push("--index-url", url);
+        }
+        for url in &self.extra_urls {
+            push("--extra-index-url", url);
+        }
+        for location in &self.find_links {
+            push("--find-links", location);
+        }
+        for package in &self.no_binary {
+            push("--no-binary", package);
+        }
+        for package in &self.only_binary {
+            push("--only-binary", package);
+        }
+        if let Some(prerelease) = &self.prerelease {
+            push("--prerelease", prerelease);
+        }
+        args
+    }
+}
+
+/// Settings read from an rt configuration file: the user config or the `rt.toml` next to the
+/// riotfile.
+#[derive(Debug, Default)]
+pub struct RtToml {
+    pub build_env: HashMap<String, String>,
+    pub run_env: HashMap<String, String>,
+    pub offline: Option<bool>,
+    pub index: IndexConfig,
+    pub parallel: Option<usize>,
+    pub python: Option<Vec<String>>,
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    /// `[[venv]]` sections, applied in order.
+    pub venvs: Vec<VenvScope>,
+}
+
+impl RtToml {
+    /// Combine with the settings of a higher-precedence file, which replace these key by key.
+    #[must_use]
+    pub fn merge(mut self, over: Self) -> Self {
+        self.build_env.extend(over.build_env);
+        self.run_env.extend(over.run_env);
+        self.venvs.extend(over.venvs);
+        Self {
+            build_env: self.build_env,
+            run_env: self.run_env,
+            offline: over.offline.or(self.offline),
+            index: self.index.merge(over.index),
+            parallel: over.parallel.or(self.parallel),
+            python: over.python.or(self.python),
+            timeout: over.timeout.or(self.timeout),
+            retries: over.retries.or(self.retries),
+            venvs: self.venvs,
+        }
+    }
+
+    /// Every value set in this file, keyed by its dotted path (e.g. `env.run.COVERAGE_FILE`).
+    #[must_use]
+    pub fn settings(&self) -> Vec<(String, toml::Value)> {
+        let strings = |values: &[String]| {
+            toml::Value::Array(values.iter().cloned().map(toml::Value::String).collect())
+        };
+        let mut settings = Vec::new();
+        if let Some(offline) = self.offline {
+            settings.push(("offline".to_string(), toml::Value::Boolean(offline)));
+        }
+        if let Some(parallel) = self.parallel {
+            settings.push((
+                "parallel".to_string(),
+                toml::Value::Integer(i64::try_from(parallel).unwrap_or(i64::MAX)),
+            ));
+        }
+        if let Some(python) = &self.python {
+            settings.push(("python".to_string(), strings(python)));
+        }
+        if let Some(timeout) = self.timeout {
+            settings.push((
+                "timeout".to_string(),
+                toml::Value::String(format_duration(timeout)),
+            ));
+        }
+        if let Some(retries) = self.retries {
+            settings.push(("retries".to_string(), toml::Value::Integer(retries.into())));
+        }
+
+        let index = &self.index;
+        if let Some(url) = &index.url {
+            settings.push(("index.url".to_string(), toml::Value::String(url.clone())));
+        }
+        for (key, values) in [
+            ("index.extra-urls", &index.extra_urls),
+            ("index.find-links", &index.find_links),
+            ("index.no-binary", &index.no_binary),
+            ("index.only-binary", &index.only_binary),
+        ] {
+            if !values.is_empty() {
+                settings.push((key.to_string(), strings(values)));
+            }
+        }
+        if let Some(prerelease) = &index.prerelease {
+            settings.push((
+                "index.prerelease".to_string(),
+                toml::Value::String(prerelease.clone()),
+            ));
+        }
+
+        push_env_settings(&mut settings, "env.build", &self.build_env);
+        push_env_settings(&mut settings, "env.run", &self.run_env);
+        settings
+    }
+}
+
+fn push_env_settings(
+    settings: &mut Vec<(String, toml::Value)>,
+    section: &str,
+    env: &HashMap<String, String>,
+) {
+    let mut keys: Vec<&String> = env.keys().collect();
+    keys.sort();
+    for key in keys {
+        settings.push((
+            format!("{section}.{key}"),
+            toml::Value::String(env[key].clone()),
+        ));
+    }
+}
+
+/// A `[[venv]]` section of rt.toml: env and extra packages for the venvs whose name matches
+/// `match` and whose Python version is listed in `python`.
+#[derive(Debug, Clone)]
+pub struct VenvScope {
+    pub pattern: Option<String>,
+    regex: Option<Regex>,
+    pub python: Option<Vec<String>>,
+    pub build_env: HashMap<String, String>,
+    pub run_env: HashMap<String, String>,
+    pub pkgs: IndexMap<String, String>,
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+impl VenvScope {
+    /// Whether this section applies to `venv`; `match` is searched in the venv name like a
+    /// selector pattern.
+    #[must_use]
+    pub fn matches(&self, venv: &RiotVenv) -> bool {
+        let name_matches = self
+            .regex
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(&venv.name).unwrap_or(false));
+        let python_matches = self
+            .python
+            .as_ref()
+            .is_none_or(|pythons| pythons.contains(&venv.python));
+        name_matches && python_matches
+    }
+
+    /// Every value set in this section, keyed by its dotted path within the section.
+    #[must_use]
+    pub fn settings(&self) -> Vec<(String, toml::Value)> {
+        let mut settings = Vec::new();
+        if let Some(pattern) = &self.pattern {
+            settings.push(("match".to_string(), toml::Value::String(pattern.clone())));
+        }
+        if let Some(python) = &self.python {
+            settings.push((
+                "python".to_string(),
+                toml::Value::Array(python.iter().cloned().map(toml::Value::String).collect()),
+            ));
+        }
+        for (name, constraint) in &self.pkgs {
+            settings.push((
+                format!("pkgs.{name}"),
+                toml::Value::String(constraint.clone()),
+            ));
+        }
+        if let Some(timeout) = self.timeout {
+            settings.push((
+                "timeout".to_string(),
+                toml::Value::String(format_duration(timeout)),
+            ));
+        }
+        if let Some(retries) = self.retries {
+            settings.push(("retries".to_string(), toml::Value::Integer(retries.into())));
+        }
+        push_env_settings(&mut settings, "env.build", &self.build_env);
+        push_env_settings(&mut settings, "env.run", &self.run_env);
+        settings
+    }
+}
+
+/// Overlay the env of the `[[venv]]` sections matching a venv on a top-level env table.
+#[must_use]
+pub fn scoped_env(
+    base: &HashMap<String, String>,
+    scoped: &IndexMap<String, String>,
+) -> HashMap<String, String> {
+    let mut env = base.clone();
+    env.extend(scoped.iter().map(|(key, val)| (key.clone(), val.clone())));
+    env
+}
+
+/// Origin of a configuration value, from lowest to highest precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    User,
+    Project,
+    CommandLine,
+}
+
+impl ConfigSource {
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Project => "project",
+            Self::CommandLine => "command line",
+        }
+    }
+}
+
+/// A configuration file rt looks for, with its settings (empty when the file does not exist).
+#[derive(Debug)]
+pub struct ConfigLayer {
+    pub source: ConfigSource,
+    pub path: Option<PathBuf>,
+    pub settings: RtToml,
+}
+
+impl ConfigLayer {
+    #[must_use]
+    pub fn exists(&self) -> bool {
+        self.path.as_ref().is_some_and(|path| path.is_file())
+    }
+}
+
+/// Location of the user configuration: `$XDG_CONFIG_HOME/rt/config.toml`, falling back to
+/// `~/.config/rt/config.toml`.
+#[must_use]
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("rt").join("config.toml"))
+}
+
+/// Load the user configuration and the project `rt.toml`, in increasing order of precedence.
+///
+/// # Errors
+///
+/// Returns an error if an existing file cannot be read or contains invalid settings.
+pub fn load_config_layers(riotfile_path: Option<&Path>) -> RtResult<Vec<ConfigLayer>> {
+    let user_path = user_config_path();
+    let project_path = riotfile_path
+        .and_then(Path::parent)
+        .map(|parent| parent.join("rt.toml"));
+
+    [
+        (ConfigSource::User, user_path),
+        (ConfigSource::Project, project_path),
+    ]
+    .into_iter()
+    .map(|(source, path)| {
+        let settings = match &path {
+            Some(path) => load_rt_toml(path)?,
+            None => RtToml::default(),
+        };
+        Ok(ConfigLayer {
+            source,
+            path,
+            settings,
+        })
+    })
+    .collect()
+}
+
+/// Merge configuration layers, later layers taking precedence.
+#[must_use]
+pub fn merge_layers(layers: Vec<ConfigLayer>) -> RtToml {
+    layers.into_iter().fold(RtToml::default(), |merged, layer| {
+        merged.merge(layer.settings)
+    })
 }
 
 pub enum Selector {
@@ -23,41 +458,66 @@ pub enum Selector {
     },
 }
 
+#[derive(Clone)]
 pub struct RunConfig {
     pub command_override: Option<String>,
     pub cmdargs: Vec<String>,
     pub action_label: String,
+    /// Only run the contexts affected by the files changed since this git base.
+    pub affected_base: Option<String>,
+    /// Only run the contexts that failed in the previous `rt run`.
+    pub rerun_failed: bool,
+    /// Only run this shard of the selected contexts, assigned with the given strategy.
+    pub shard: Option<(Shard, ShardBy)>,
+    /// Merged `JUnit` XML report to write.
+    pub junit_xml: Option<PathBuf>,
+    /// Per-context timeout given on the command line.
+    pub timeout: Option<Duration>,
+    /// Retries of a failed context given on the command line.
+    pub retries: Option<u32>,
+    /// Stop after the first failed build step or execution context.
+    pub fail_fast: Option<FailFast>,
 }
 
 impl RepoConfig {
+    /// Combine the located paths with the merged configuration files; `offline` is the
+    /// command-line flag, which can only enable offline mode, and `events` the progress event
+    /// format requested on the command line.
     #[must_use]
     pub fn load(
         riotfile_path: PathBuf,
         riot_root: PathBuf,
-        build_env: HashMap<String, String>,
-        run_env: HashMap<String, String>,
+        rt_toml: RtToml,
+        offline: bool,
+        events: Option<EventFormat>,
     ) -> Self {
         Self {
             riotfile_path,
             riot_root,
-            build_env: Arc::new(build_env),
-            run_env: Arc::new(run_env),
+            build_env: Arc::new(rt_toml.build_env),
+            run_env: Arc::new(rt_toml.run_env),
+            offline: offline || rt_toml.offline.unwrap_or(false),
+            index: Arc::new(rt_toml.index),
+            parallel: rt_toml.parallel,
+            python: rt_toml.python,
+            timeout: rt_toml.timeout,
+            retries: rt_toml.retries,
+            events,
         }
     }
 }
 
-pub fn load_rt_toml(
-    riotfile_path: &Path,
-) -> RtResult<(HashMap<String, String>, HashMap<String, String>)> {
-    let Some(parent_dir) = riotfile_path.parent() else {
-        return Ok((HashMap::new(), HashMap::new()));
-    };
-    let config_path = parent_dir.join("rt.toml");
+/// Parse one configuration file; a missing file has no settings.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or contains invalid settings.
+pub fn load_rt_toml(config_path: &Path) -> RtResult<RtToml> {
     if !config_path.is_file() {
-        return Ok((HashMap::new(), HashMap::new()));
+        return Ok(RtToml::default());
     }
 
-    let contents = fs::read_to_string(&config_path).map_err(|err| {
+    let contents = fs::read_to_string(config_path).map_err(|err| {
         RtError::message(format!(
             "error: failed to read {}: {err}",
             config_path.display()
@@ -74,8 +534,217 @@ pub fn load_rt_toml(
     let env_table = parsed.get("env").and_then(|val| val.as_table());
     let build_env = parse_env_table(env_table.and_then(|tbl| tbl.get("build")), "env.build")?;
     let run_env = parse_env_table(env_table.and_then(|tbl| tbl.get("run")), "env.run")?;
+    let offline = parse_bool(parsed.get("offline"), "offline")?;
+    let index = parse_index_table(parsed.get("index"))?;
+    let parallel = parsed
+        .get("parallel")
+        .map(|val| {
+            val.as_integer()
+                .and_then(|count| usize::try_from(count).ok())
+                .filter(|count| *count > 0)
+                .ok_or_else(|| RtError::message("error: parallel must be a positive integer"))
+        })
+        .transpose()?;
+    let python = parsed
+        .get("python")
+        .map(|val| parse_string_list(val, "python"))
+        .transpose()?;
+    let timeout = parsed
+        .get("timeout")
+        .map(|val| parse_timeout(val, "timeout"))
+        .transpose()?;
+    let retries = parsed
+        .get("retries")
+        .map(|val| parse_retries(val, "retries"))
+        .transpose()?;
+    let venvs = parse_venv_scopes(parsed.get("venv"))?;
+
+    Ok(RtToml {
+        build_env,
+        run_env,
+        offline,
+        index,
+        parallel,
+        python,
+        timeout,
+        retries,
+        venvs,
+    })
+}
+
+fn parse_venv_scopes(value: Option<&toml::Value>) -> RtResult<Vec<VenvScope>> {
+    let Some(val) = value else {
+        return Ok(Vec::new());
+    };
+    let Some(sections) = val.as_array() else {
+        return Err(RtError::message(
+            "error: venv must be an array of tables ([[venv]])",
+        ));
+    };
+
+    sections
+        .iter()
+        .enumerate()
+        .map(|(idx, section)| {
+            let Some(table) = section.as_table() else {
+                return Err(RtError::message(format!("error: venv[{idx}] must be a table")));
+            };
+            let mut scope = VenvScope {
+                pattern: None,
+                regex: None,
+                python: None,
+                build_env: HashMap::new(),
+                run_env: HashMap::new(),
+                pkgs: IndexMap::new(),
+                timeout: None,
+                retries: None,
+            };
+            for (key, val) in table {
+                match key.as_str() {
+                    "match" => {
+                        let pattern = parse_string(val, &format!("venv[{idx}].match"))?;
+                        let regex = Regex::new(&pattern).map_err(|err| {
+                            RtError::message(format!(
+                                "error: venv[{idx}].match is not a valid pattern: {err}"
+                            ))
+                        })?;
+                        scope.pattern = Some(pattern);
+                        scope.regex = Some(regex);
+                    }
+                    "python" => {
+                        scope.python = Some(parse_string_list(val, &format!("venv[{idx}].python"))?);
+                    }
+                    "pkgs" => {
+                        scope.pkgs = parse_env_table(Some(val), &format!("venv[{idx}].pkgs"))?
+                            .into_iter()
+                            .collect();
+                        scope.pkgs.sort_keys();
+                    }
+                    "timeout" => {
+                        scope.timeout = Some(parse_timeout(val, &format!("venv[{idx}].timeout"))?);
+                    }
+                    "retries" => {
+                        scope.retries = Some(parse_retries(val, &format!("venv[{idx}].retries"))?);
+                    }
+                    "env" => {
+                        let env_table = val.as_table();
+                        scope.build_env = parse_env_table(
+                            env_table.and_then(|tbl| tbl.get("build")),
+                            &format!("venv[{idx}].env.build"),
+                        )?;
+                        scope.run_env = parse_env_table(
+                            env_table.and_then(|tbl| tbl.get("run")),
+                            &format!("venv[{idx}].env.run"),
+                        )?;
+                    }
+                    _ => {
+                        return Err(RtError::message(format!(
+                            "error: unknown key venv[{idx}].{key} (expected match, python, pkgs, timeout, retries or env)"
+                        )));
+                    }
+                }
+            }
+            Ok(scope)
+        })
+        .collect()
+}
+
+fn parse_index_table(value: Option<&toml::Value>) -> RtResult<IndexConfig> {
+    let Some(val) = value else {
+        return Ok(IndexConfig::default());
+    };
+    let Some(table) = val.as_table() else {
+        return Err(RtError::message("error: index must be a table"));
+    };
+
+    let mut index = IndexConfig::default();
+    for (key, val) in table {
+        match key.as_str() {
+            "url" => index.url = Some(parse_string(val, "index.url")?),
+            "extra-urls" => index.extra_urls = parse_string_list(val, "index.extra-urls")?,
+            "find-links" => index.find_links = parse_string_list(val, "index.find-links")?,
+            "no-binary" => index.no_binary = parse_string_list(val, "index.no-binary")?,
+            "only-binary" => index.only_binary = parse_string_list(val, "index.only-binary")?,
+            "prerelease" => {
+                let policy = parse_string(val, "index.prerelease")?;
+                if !PRERELEASE_POLICIES.contains(&policy.as_str()) {
+                    return Err(RtError::message(format!(
+                        "error: index.prerelease must be one of {}, got \"{policy}\"",
+                        PRERELEASE_POLICIES.join(", ")
+                    )));
+                }
+                index.prerelease = Some(policy);
+            }
+            _ => {
+                return Err(RtError::message(format!(
+                    "error: unknown key index.{key} (expected url, extra-urls, find-links, no-binary, only-binary or prerelease)"
+                )));
+            }
+        }
+    }
+
+    Ok(index)
+}
+
+fn parse_string(value: &toml::Value, key: &str) -> RtResult<String> {
+    value
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| RtError::message(format!("error: {key} must be a string")))
+}
+
+fn parse_string_list(value: &toml::Value, key: &str) -> RtResult<Vec<String>> {
+    let Some(items) = value.as_array() else {
+        return Err(RtError::message(format!(
+            "error: {key} must be an array of strings"
+        )));
+    };
+    items
+        .iter()
+        .map(|item| {
+            item.as_str().map(str::to_string).ok_or_else(|| {
+                RtError::message(format!("error: {key} must be an array of strings"))
+            })
+        })
+        .collect()
+}
+
+/// A timeout given as a duration string (e.g. `"30m"`) or a number of seconds.
+fn parse_timeout(value: &toml::Value, key: &str) -> RtResult<Duration> {
+    let timeout = match value {
+        toml::Value::String(text) => parse_duration(text).map_err(|err| {
+            RtError::message(format!("error: {key} is not a valid duration: {err}"))
+        })?,
+        toml::Value::Integer(seconds) => u64::try_from(*seconds)
+            .map(Duration::from_secs)
+            .map_err(|_| RtError::message(format!("error: {key} must not be negative")))?,
+        _ => {
+            return Err(RtError::message(format!(
+                "error: {key} must be a duration string (e.g. \"30m\") or a number of seconds"
+            )));
+        }
+    };
+    if timeout.is_zero() {
+        return Err(RtError::message(format!("error: {key} must be positive")));
+    }
+    Ok(timeout)
+}
+
+/// A number of retries, zero disabling them.
+fn parse_retries(value: &toml::Value, key: &str) -> RtResult<u32> {
+    value
+        .as_integer()
+        .and_then(|count| u32::try_from(count).ok())
+        .ok_or_else(|| RtError::message(format!("error: {key} must be a non-negative integer")))
+}
 
-    Ok((build_env, run_env))
+fn parse_bool(value: Option<&toml::Value>, key: &str) -> RtResult<Option<bool>> {
+    value
+        .map(|val| {
+            val.as_bool()
+                .ok_or_else(|| RtError::message(format!("error: {key} must be a boolean")))
+        })
+        .transpose()
 }
 
 fn parse_env_table(
@@ -105,3 +774,264 @@ fn parse_env_table(
 
     Ok(env)
 }
+
+/// Parse a human duration such as `90s`, `15m`, `1h30m`, `7d` or `2w` (bare numbers are seconds).
+///
+/// # Errors
+///
+/// Returns a description of the problem when the value is not a valid duration.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("duration cannot be empty".to_string());
+    }
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = 0u64;
+    let mut digits = String::new();
+    for ch in value.chars() {
+        if ch.is_ascii_digit() {
+            digits.push(ch);
+            continue;
+        }
+        let unit = match ch {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(format!("invalid duration unit '{ch}' in '{value}'")),
+        };
+        let amount: u64 = digits
+            .parse()
+            .map_err(|_| format!("missing amount before '{ch}' in '{value}'"))?;
+        total = total.saturating_add(amount.saturating_mul(unit));
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(format!("missing unit after '{digits}' in '{value}'"));
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+/// Format a duration the way `parse_duration` reads it, e.g. `1h30m`.
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let mut remaining = duration.as_secs();
+    if remaining == 0 {
+        return "0s".to_string();
+    }
+    let mut formatted = String::new();
+    for (unit, secs) in [
+        ('w', 7 * 24 * 60 * 60),
+        ('d', 24 * 60 * 60),
+        ('h', 60 * 60),
+        ('m', 60),
+        ('s', 1),
+    ] {
+        if remaining >= secs {
+            let _ = write!(formatted, "{}{unit}", remaining / secs);
+            remaining %= secs;
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use indexmap::IndexMap;
+
+    use super::{
+        InterpolationVars, RtToml, format_duration, parse_duration, parse_index_table,
+        parse_venv_scopes,
+    };
+    use crate::{
+        commands::build::{deps_key, requirements_content},
+        venv::{RiotVenv, apply_venv_scopes, lockfile_problems},
+    };
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_mins(15)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_mins(90)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_hours(168)));
+        assert_eq!(format_duration(Duration::from_mins(90)), "1h30m");
+        let odd = Duration::from_secs(694_861);
+        assert_eq!(parse_duration(&format_duration(odd)), Ok(odd));
+    }
+
+    #[test]
+    fn parse_duration_rejects_malformed_values() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    #[test]
+    fn expands_rt_variables_and_defaults() {
+        let vars = InterpolationVars::default()
+            .python("3.12")
+            .venv("flask", "2b340ba@13515d1");
+        assert_eq!(
+            vars.expand("${RT_PROJECT_ROOT:-.}/.coverage.${RT_VENV_NAME}.${RT_VENV_HASH}"),
+            "./.coverage.flask.2b340ba@13515d1"
+        );
+        assert_eq!(
+            vars.expand("py${RT_PYTHON}-${RT_UNSET_FOR_TEST}"),
+            "py3.12-"
+        );
+        assert_eq!(
+            vars.expand("literal ${unterminated"),
+            "literal ${unterminated"
+        );
+    }
+
+    #[test]
+    fn project_settings_override_user_settings_key_by_key() {
+        let env = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(key, val)| ((*key).to_string(), (*val).to_string()))
+                .collect()
+        };
+        let user = RtToml {
+            run_env: env(&[("A", "user"), ("B", "user")]),
+            parallel: Some(8),
+            python: Some(vec!["3.12".to_string()]),
+            ..RtToml::default()
+        };
+        let project = RtToml {
+            run_env: env(&[("B", "project")]),
+            python: Some(vec!["3.13".to_string()]),
+            ..RtToml::default()
+        };
+
+        let merged = user.merge(project);
+        assert_eq!(merged.run_env["A"], "user");
+        assert_eq!(merged.run_env["B"], "project");
+        assert_eq!(merged.parallel, Some(8));
+        assert_eq!(merged.python, Some(vec!["3.13".to_string()]));
+    }
+
+    #[test]
+    fn venv_section_pkgs_go_on_top_of_lockfile_pins() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            [[venv]]
+            match = "flask"
+            pkgs = { coverage = ">=7", werkzeug = "<3" }
+            "#,
+        )
+        .unwrap();
+        let scopes = parse_venv_scopes(value.get("venv")).unwrap();
+
+        let mut pkgs = IndexMap::new();
+        pkgs.insert("flask".to_string(), "~=2.0".to_string());
+        let mut venv = RiotVenv::new(
+            "flask".to_string(),
+            "3.12".to_string(),
+            pkgs,
+            "2b340ba".to_string(),
+            Vec::new(),
+        );
+        venv.resolved_pkgs
+            .insert("flask".to_string(), "2.3.3".to_string());
+        venv.resolved_pkgs
+            .insert("Werkzeug".to_string(), "3.0.1".to_string());
+        let unscoped_key = deps_key(&venv);
+
+        let mut venvs = IndexMap::new();
+        venvs.insert(venv.hash.clone(), venv);
+        apply_venv_scopes(&mut venvs, &scopes);
+        let venv = &venvs["2b340ba"];
+
+        assert_eq!(
+            requirements_content(venv),
+            "flask==2.3.3\ncoverage>=7\nwerkzeug<3\n"
+        );
+        assert_ne!(deps_key(venv), unscoped_key);
+        assert!(lockfile_problems(&venv.pkgs, &venv.resolved_pkgs).is_empty());
+    }
+
+    #[test]
+    fn venv_sections_parse_scoped_settings() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            [[venv]]
+            match = "django.*"
+            python = ["3.12"]
+            pkgs = { psycopg2-binary = ">=2.9" }
+            env.build = { CFLAGS = "-O0" }
+            env.run = { DJANGO_DB = "postgres" }
+            timeout = "20m"
+            retries = 2
+            "#,
+        )
+        .unwrap();
+        let scopes = parse_venv_scopes(value.get("venv")).unwrap();
+        assert_eq!(scopes.len(), 1);
+        assert_eq!(scopes[0].pattern.as_deref(), Some("django.*"));
+        assert_eq!(scopes[0].pkgs["psycopg2-binary"], ">=2.9");
+        assert_eq!(scopes[0].build_env["CFLAGS"], "-O0");
+        assert_eq!(scopes[0].run_env["DJANGO_DB"], "postgres");
+        assert_eq!(scopes[0].timeout, Some(Duration::from_mins(20)));
+        assert_eq!(scopes[0].retries, Some(2));
+
+        for invalid in [
+            "[[venv]]\nmatch = \"(\"",
+            "[[venv]]\nservices = []",
+            "venv = { match = \"x\" }",
+            "[[venv]]\ntimeout = \"0s\"",
+            "[[venv]]\nretries = -1",
+        ] {
+            let value: toml::Value = toml::from_str(invalid).unwrap();
+            assert!(parse_venv_scopes(value.get("venv")).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn index_table_maps_to_uv_arguments() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            url = "https://mirror.example/simple"
+            extra-urls = ["https://extra.example/simple"]
+            only-binary = [":all:"]
+            prerelease = "allow"
+            "#,
+        )
+        .unwrap();
+        let index = parse_index_table(Some(&value)).unwrap();
+        assert_eq!(
+            index.args(),
+            [
+                "--index-url",
+                "https://mirror.example/simple",
+                "--extra-index-url",
+                "https://extra.example/simple",
+                "--only-binary",
+                ":all:",
+                "--prerelease",
+                "allow",
+            ]
+        );
+    }
+
+    #[test]
+    fn index_table_rejects_invalid_settings() {
+        for invalid in [
+            "prerelease = \"sometimes\"",
+            "extra-urls = \"https://extra.example/simple\"",
+            "mirror = \"https://mirror.example\"",
+        ] {
+            let value: toml::Value = toml::from_str(invalid).unwrap();
+            assert!(parse_index_table(Some(&value)).is_err(), "{invalid}");
+        }
+    }
+}
@@ -10,6 +10,8 @@ pub use pyo3::Pyo3ConfigProvider;
 pub use pyo3::PyVenv;
 
 pub type ProviderServices = HashMap<String, Vec<String>>;
+/// Path patterns of each suitespec suite, with `@component` references expanded.
+pub type ProviderSuitePaths = HashMap<String, Vec<String>>;
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub struct ProviderVenvNode {
@@ -27,6 +29,7 @@ pub struct ProviderVenvNode {
 pub struct LoadedConfig {
     pub root: ProviderVenvNode,
     pub services: Option<ProviderServices>,
+    pub suite_paths: Option<ProviderSuitePaths>,
 }
 
 pub trait ConfigProvider {
@@ -5,7 +5,9 @@ use pyo3::prelude::*;
 use pyo3::types::{PyAny, PyAnyMethods, PyDict, PyIterator, PyModule, PyString, PyStringMethods};
 
 use crate::{
-    config_provider::{ConfigProvider, LoadedConfig, ProviderServices, ProviderVenvNode},
+    config_provider::{
+        ConfigProvider, LoadedConfig, ProviderServices, ProviderSuitePaths, ProviderVenvNode,
+    },
     error::{RtError, RtResult},
 };
 
@@ -88,9 +90,13 @@ impl ConfigProvider for Pyo3ConfigProvider {
             py.import("gc")?.call_method0("disable")?;
 
             let root = load_riotfile(py, riotfile_path)?;
-            let services = get_services(py, project_path)?;
+            let (services, suite_paths) = get_suitespec(py, project_path).unzip();
 
-            Ok(LoadedConfig { root, services })
+            Ok(LoadedConfig {
+                root,
+                services,
+                suite_paths,
+            })
         })
     }
 }
@@ -178,26 +184,36 @@ fn load_riotfile(py: Python<'_>, path: &Path) -> RtResult<ProviderVenvNode> {
     Ok(venv.into())
 }
 
-fn get_services(py: Python<'_>, project_path: &Path) -> RtResult<Option<ProviderServices>> {
+fn get_suitespec(
+    py: Python<'_>,
+    project_path: &Path,
+) -> Option<(ProviderServices, ProviderSuitePaths)> {
     let python_code = format!(
         "import sys; sys.path.insert(0, r\"{}\"); from tests.suitespec import SUITESPEC",
         project_path.to_string_lossy()
     );
 
-    let Some(code_cstr) = CString::new(python_code).ok() else {
-        return Ok(None);
-    };
+    let code_cstr = CString::new(python_code).ok()?;
 
-    Ok(extract_suitespec_services(py, &code_cstr).ok())
+    extract_suitespec(py, &code_cstr).ok()
 }
 
-fn extract_suitespec_services(py: Python<'_>, code: &CString) -> PyResult<ProviderServices> {
+fn extract_suitespec(
+    py: Python<'_>,
+    code: &CString,
+) -> PyResult<(ProviderServices, ProviderSuitePaths)> {
     let module = PyModule::from_code(py, code.as_c_str(), c"get_services", c"_get_service")?;
     let suitespec = module.getattr("SUITESPEC")?;
     let suites_obj = suitespec.get_item("suites")?;
     let suites: &Bound<'_, PyDict> = suites_obj.cast()?;
+    let components: HashMap<String, Vec<String>> = suitespec
+        .get_item("components")
+        .ok()
+        .and_then(|components| components.extract().ok())
+        .unwrap_or_default();
 
     let mut result: HashMap<String, Vec<String>> = HashMap::new();
+    let mut suite_paths: ProviderSuitePaths = HashMap::new();
 
     for (key, value) in suites {
         let full_name: String = key.extract()?;
@@ -211,6 +227,19 @@ fn extract_suitespec_services(py: Python<'_>, code: &CString) -> PyResult<Provid
         let services: Option<Vec<String>> =
             dict.get_item("services")?.and_then(|v| v.extract().ok());
         let snapshot: Option<bool> = dict.get_item("snapshot")?.and_then(|v| v.extract().ok());
+        let paths: Option<Vec<String>> = dict.get_item("paths")?.and_then(|v| v.extract().ok());
+        if let Some(paths) = paths {
+            let expanded = paths
+                .into_iter()
+                .flat_map(|path| {
+                    path.strip_prefix('@').map_or_else(
+                        || vec![path.clone()],
+                        |component| components.get(component).cloned().unwrap_or_default(),
+                    )
+                })
+                .collect();
+            suite_paths.insert(suite_name.clone(), expanded);
+        }
 
         let mut svc_list = services.unwrap_or_default();
         if snapshot.unwrap_or(false) {
@@ -222,7 +251,7 @@ fn extract_suitespec_services(py: Python<'_>, code: &CString) -> PyResult<Provid
         }
     }
 
-    Ok(result)
+    Ok((result, suite_paths))
 }
 
 // ---------------------------------------------------------------------------
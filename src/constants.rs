@@ -3,9 +3,12 @@
 /// Prefix used for all virtual environment directories
 pub const VENV_PREFIX: &str = "venv_";
 
-/// Marker file indicating a virtual environment has been fully built
+/// Marker file holding the build manifest of a fully built directory
 pub const DONE_MARKER: &str = ".riot_done";
 
+/// Marker file of a build tree, locked shared by every process running from the tree
+pub const IN_USE_MARKER: &str = ".riot_in_use";
+
 /// Requirements directory name under riot root
 pub const REQUIREMENTS_DIR: &str = "requirements";
 
@@ -14,3 +17,12 @@ pub const VENV_SELF_DIR: &str = "venv_self";
 
 /// Dependencies install directory name
 pub const VENV_DEPS_DIR: &str = "venv_deps";
+
+/// Directory under riot root holding per-target build lock files
+pub const LOCKS_DIR: &str = ".locks";
+
+/// File under riot root recording the outcome of every `rt run`
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// Directory under riot root holding the output of build and run steps
+pub const LOGS_DIR: &str = "logs";
@@ -92,9 +92,44 @@ fn truncate_line(line: &str, max_width: usize) -> String {
 pub enum StepStatus {
     Pending,
     Running,
+    /// Blocked on a build lock held by another rt process.
+    Waiting,
     Done,
     Cached,
     Failed,
+    /// Stopped by rt after exceeding its timeout.
+    TimedOut,
+    /// Stopped by rt before it finished, on SIGINT or SIGTERM or by `--fail-fast=terminate`, or
+    /// never started because of SIGINT or SIGTERM.
+    Interrupted,
+    /// Not started because an earlier step failed in fail-fast mode.
+    Skipped,
+    /// Succeeded only after being retried.
+    Flaky,
+}
+
+impl StepStatus {
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Waiting => "waiting",
+            Self::Done => "done",
+            Self::Cached => "cached",
+            Self::Failed => "failed",
+            Self::TimedOut => "timed-out",
+            Self::Interrupted => "interrupted",
+            Self::Skipped => "skipped",
+            Self::Flaky => "flaky",
+        }
+    }
+
+    /// Whether the step ended unsuccessfully.
+    #[must_use]
+    pub const fn is_failure(self) -> bool {
+        matches!(self, Self::Failed | Self::TimedOut | Self::Interrupted)
+    }
 }
 
 /// Format a status icon with appropriate color and styling.
@@ -103,9 +138,14 @@ fn status_icon(status: StepStatus) -> String {
     match status {
         StepStatus::Pending => "[pending]".with(Color::DarkGrey).to_string(),
         StepStatus::Running => "[running]".with(Color::Cyan).to_string(),
+        StepStatus::Waiting => "[waiting]".with(Color::Magenta).to_string(),
         StepStatus::Done => "[done]".with(Color::Green).to_string(),
         StepStatus::Cached => "[cached]".with(Color::Yellow).to_string(),
         StepStatus::Failed => "[failed]".with(Color::Red).to_string(),
+        StepStatus::TimedOut => "[timed out]".with(Color::Red).to_string(),
+        StepStatus::Interrupted => "[interrupted]".with(Color::Red).to_string(),
+        StepStatus::Skipped => "[skipped]".with(Color::DarkYellow).to_string(),
+        StepStatus::Flaky => "[flaky]".with(Color::DarkMagenta).to_string(),
     }
 }
 
@@ -218,12 +258,18 @@ impl BuildStep {
     pub fn update_status(&mut self, status: StepStatus) {
         self.status = status;
         match status {
-            StepStatus::Running => {
+            StepStatus::Running | StepStatus::Waiting => {
                 if self.start_time.is_none() {
                     self.start_time = Some(Instant::now());
                 }
             }
-            StepStatus::Done | StepStatus::Cached | StepStatus::Failed => {
+            StepStatus::Done
+            | StepStatus::Cached
+            | StepStatus::Failed
+            | StepStatus::TimedOut
+            | StepStatus::Interrupted
+            | StepStatus::Skipped
+            | StepStatus::Flaky => {
                 if self.end_time.is_none() {
                     self.end_time = Some(Instant::now());
                 }
@@ -237,7 +283,7 @@ impl BuildStep {
     pub const fn is_fully_collapsed(&self) -> bool {
         matches!(
             self.status,
-            StepStatus::Pending | StepStatus::Done | StepStatus::Cached
+            StepStatus::Pending | StepStatus::Waiting | StepStatus::Done | StepStatus::Cached
         )
     }
 
@@ -312,9 +358,14 @@ pub struct DisplayManager {
 impl DisplayManager {
     const GROUP_ORDER: &[(StepStatus, usize)] = &[
         (StepStatus::Failed, FAILED_BLOCK_LINES),
+        (StepStatus::TimedOut, FAILED_BLOCK_LINES),
+        (StepStatus::Interrupted, FAILED_BLOCK_LINES),
+        (StepStatus::Waiting, COLLAPSED_LINE_COST),
         (StepStatus::Pending, COLLAPSED_LINE_COST),
+        (StepStatus::Flaky, COLLAPSED_LINE_COST),
         (StepStatus::Done, COLLAPSED_LINE_COST),
         (StepStatus::Cached, COLLAPSED_LINE_COST),
+        (StepStatus::Skipped, COLLAPSED_LINE_COST),
     ];
 
     /// Create a new `DisplayManager`.
@@ -550,7 +601,14 @@ impl DisplayManager {
         steps.values().all(|s| {
             matches!(
                 s.status,
-                StepStatus::Done | StepStatus::Cached | StepStatus::Failed | StepStatus::Pending
+                StepStatus::Done
+                    | StepStatus::Cached
+                    | StepStatus::Failed
+                    | StepStatus::TimedOut
+                    | StepStatus::Interrupted
+                    | StepStatus::Skipped
+                    | StepStatus::Flaky
+                    | StepStatus::Pending
             )
         })
     }
@@ -657,7 +715,7 @@ impl DisplayManager {
 
         match (step.status, style) {
             (
-                StepStatus::Failed,
+                StepStatus::Failed | StepStatus::TimedOut | StepStatus::Interrupted,
                 StepRenderStyle::Active {
                     terminal_width,
                     remaining_height,
@@ -668,7 +726,10 @@ impl DisplayManager {
                 let max_output = max_lines.saturating_sub(1);
                 step.render_expanded(max_output, Some(terminal_width), true)
             }
-            (StepStatus::Failed, StepRenderStyle::Final) => step.render_expanded(30, None, false),
+            (
+                StepStatus::Failed | StepStatus::TimedOut | StepStatus::Interrupted,
+                StepRenderStyle::Final,
+            ) => step.render_expanded(30, None, false),
             (
                 StepStatus::Running,
                 StepRenderStyle::Active {
@@ -686,10 +747,7 @@ impl DisplayManager {
     }
 
     fn lines_per_running(steps: &[&BuildStep], available_height: usize) -> usize {
-        let failed_count = steps
-            .iter()
-            .filter(|s| s.status == StepStatus::Failed)
-            .count();
+        let failed_count = steps.iter().filter(|s| s.status.is_failure()).count();
         let running_count = steps
             .iter()
             .filter(|s| s.status == StepStatus::Running)
@@ -697,11 +755,9 @@ impl DisplayManager {
 
         let lines_for_failed = failed_count * FAILED_BLOCK_LINES;
         let remaining_lines = available_height.saturating_sub(lines_for_failed);
-        if running_count > 0 {
-            (remaining_lines / running_count).max(RUNNING_MIN_LINES)
-        } else {
-            0
-        }
+        remaining_lines
+            .checked_div(running_count)
+            .map_or(0, |lines| lines.max(RUNNING_MIN_LINES))
     }
 
     fn format_duration(duration: Duration) -> String {
@@ -722,27 +778,30 @@ impl DisplayManager {
         terminal_width: usize,
         start_time: Instant,
     ) -> String {
-        let mut counts = (0usize, 0usize, 0usize, 0usize, 0usize);
-        for step in steps.values() {
-            match step.status {
-                StepStatus::Pending => counts.0 += 1,
-                StepStatus::Running => counts.1 += 1,
-                StepStatus::Done => counts.2 += 1,
-                StepStatus::Cached => counts.3 += 1,
-                StepStatus::Failed => counts.4 += 1,
-            }
-        }
-
-        let (pending, running, done, cached, failed) = counts;
+        let count = |status: StepStatus| steps.values().filter(|s| s.status == status).count();
+        let running = count(StepStatus::Running) + count(StepStatus::Waiting);
+        let pending = count(StepStatus::Pending);
         let total_steps = steps.len();
-        let completed = done + cached + failed;
-        let parts = [
+        let completed = total_steps - running - pending;
+        let mut parts = vec![
             (StepStatus::Running, running),
             (StepStatus::Pending, pending),
-            (StepStatus::Done, done),
-            (StepStatus::Cached, cached),
-            (StepStatus::Failed, failed),
+            (StepStatus::Done, count(StepStatus::Done)),
+            (StepStatus::Cached, count(StepStatus::Cached)),
+            (StepStatus::Failed, count(StepStatus::Failed)),
         ];
+        // Only shown when they happen, to keep the summary short.
+        for status in [
+            StepStatus::Flaky,
+            StepStatus::TimedOut,
+            StepStatus::Interrupted,
+            StepStatus::Skipped,
+        ] {
+            let count = count(status);
+            if count > 0 {
+                parts.push((status, count));
+            }
+        }
 
         let mut line = String::from("Summary: ");
         let mut first = true;
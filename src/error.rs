@@ -9,6 +9,17 @@ pub type RtResult<T> = Result<T, RtError>;
 pub struct RtError {
     exit_code: u8,
     message: Option<String>,
+    termination: Option<Termination>,
+}
+
+/// Why rt stopped a command instead of letting it exit on its own, or never started it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    TimedOut,
+    /// rt received SIGINT or SIGTERM.
+    Interrupted,
+    /// Not started because an earlier step failed.
+    Skipped,
 }
 
 impl RtError {
@@ -17,6 +28,7 @@ impl RtError {
         Self {
             exit_code: 1,
             message: Some(message.into()),
+            termination: None,
         }
     }
 
@@ -25,6 +37,7 @@ impl RtError {
         Self {
             exit_code,
             message: Some(message.into()),
+            termination: None,
         }
     }
 
@@ -33,6 +46,7 @@ impl RtError {
         Self {
             exit_code,
             message: None,
+            termination: None,
         }
     }
 
@@ -41,11 +55,25 @@ impl RtError {
         Self::silent(status_exit_code(status))
     }
 
+    /// Record that rt stopped the command, e.g. because it timed out.
+    #[must_use]
+    pub fn terminated(self, termination: Termination) -> Self {
+        Self {
+            termination: Some(termination),
+            ..self
+        }
+    }
+
     #[must_use]
     pub const fn exit_code(&self) -> u8 {
         self.exit_code
     }
 
+    #[must_use]
+    pub const fn termination(&self) -> Option<Termination> {
+        self.termination
+    }
+
     pub fn report(&self) {
         if let Some(message) = &self.message {
             eprintln!("{message}");
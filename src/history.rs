@@ -0,0 +1,189 @@
+//! Run history: the outcome of every `rt run`, appended as one JSON line per invocation to
+//! `history.jsonl` under the riot root.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::HISTORY_FILE,
+    error::{RtError, RtResult},
+};
+
+/// Outcome of one execution context within a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContextStatus {
+    Passed,
+    Failed,
+    TimedOut,
+    /// Stopped, or never started, because rt was interrupted.
+    Interrupted,
+    /// Not started because another context failed in fail-fast mode.
+    Skipped,
+    /// Passed only after being retried.
+    Flaky,
+}
+
+impl ContextStatus {
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Passed => "passed",
+            Self::Failed => "failed",
+            Self::TimedOut => "timed out",
+            Self::Interrupted => "interrupted",
+            Self::Skipped => "skipped",
+            Self::Flaky => "flaky",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextResult {
+    pub hash: String,
+    pub name: String,
+    pub python: String,
+    pub command: String,
+    pub status: ContextStatus,
+    pub exit_code: u8,
+    /// Seconds since the Unix epoch.
+    pub started_at: f64,
+    /// Total over all attempts.
+    pub duration_secs: f64,
+    /// Executions of the command, more than one when it was retried.
+    #[serde(default = "single_attempt")]
+    pub attempts: u32,
+    /// Why a skipped context was not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<String>,
+}
+
+const fn single_attempt() -> u32 {
+    1
+}
+
+/// One `rt run` invocation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Seconds since the Unix epoch.
+    pub started_at: f64,
+    pub duration_secs: f64,
+    pub command_override: Option<String>,
+    pub cmdargs: Vec<String>,
+    pub results: Vec<ContextResult>,
+}
+
+impl RunRecord {
+    /// Contexts that did not pass, including skipped and interrupted ones but not flaky ones.
+    pub fn failed(&self) -> impl Iterator<Item = &ContextResult> {
+        self.results
+            .iter()
+            .filter(|result| !matches!(result.status, ContextStatus::Passed | ContextStatus::Flaky))
+    }
+}
+
+#[must_use]
+pub fn history_path(riot_root: &Path) -> PathBuf {
+    riot_root.join(HISTORY_FILE)
+}
+
+/// Seconds since the Unix epoch for `time`.
+#[must_use]
+pub fn epoch_secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs_f64()
+}
+
+/// Append a run to the history of `riot_root`.
+///
+/// # Errors
+///
+/// Returns an error if the history file cannot be written.
+pub fn append(riot_root: &Path, record: &RunRecord) -> RtResult<()> {
+    let line = serde_json::to_string(record).map_err(|err| {
+        RtError::message(format!("error: failed to serialize run history: {err}"))
+    })?;
+    fs::create_dir_all(riot_root)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_path(riot_root))?;
+    writeln!(file, "{line}")?;
+    Ok(())
+}
+
+/// Every recorded run of `riot_root`, oldest first. Lines that cannot be parsed are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the history file exists but cannot be read.
+pub fn load(riot_root: &Path) -> RtResult<Vec<RunRecord>> {
+    let path = history_path(riot_root);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path)?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContextResult, ContextStatus, RunRecord, append, load};
+
+    #[test]
+    fn appends_and_reloads_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = |hash: &str, status| ContextResult {
+            hash: hash.to_string(),
+            name: "flask".to_string(),
+            python: "3.12".to_string(),
+            command: "pytest tests/".to_string(),
+            status,
+            exit_code: u8::from(status == ContextStatus::Failed),
+            started_at: 1.0,
+            duration_secs: 2.5,
+            attempts: 1,
+            skip_reason: None,
+        };
+        for failed in [ContextStatus::Failed, ContextStatus::Flaky] {
+            append(
+                dir.path(),
+                &RunRecord {
+                    started_at: 1.0,
+                    duration_secs: 3.0,
+                    command_override: None,
+                    cmdargs: vec!["-k".to_string(), "views".to_string()],
+                    results: vec![
+                        result("2b340ba@13515d1", ContextStatus::Passed),
+                        result("30a07c0@13515d1", failed),
+                    ],
+                },
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            dir.path().join("history.jsonl"),
+            std::fs::read_to_string(dir.path().join("history.jsonl")).unwrap() + "{truncated\n",
+        )
+        .unwrap();
+
+        let runs = load(dir.path()).unwrap();
+        assert_eq!(runs.len(), 2);
+        let failed: Vec<&str> = runs[0]
+            .failed()
+            .map(|result| result.hash.as_str())
+            .collect();
+        assert_eq!(failed, ["30a07c0@13515d1"]);
+        assert_eq!(runs[1].failed().count(), 0);
+    }
+}
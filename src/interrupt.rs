@@ -0,0 +1,166 @@
+//! Ctrl-C handling: SIGINT and SIGTERM are forwarded to the commands rt is running, and tasks that
+//! have not started yet are cancelled instead of being run. A fail-fast stop terminates the
+//! running commands the same way.
+
+use std::{
+    sync::{
+        Arc, LazyLock, Mutex, Once, PoisonError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+static INTERRUPTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(|| Arc::new(AtomicBool::new(false)));
+
+static CHILDREN: Mutex<Vec<RunningChild>> = Mutex::new(Vec::new());
+
+/// Whether rt received SIGINT or SIGTERM.
+#[must_use]
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Flag telling tasks to abandon their work, because rt was interrupted or a fail-fast stop
+/// terminates the tasks still running.
+#[derive(Clone, Debug)]
+pub struct Cancellation {
+    interrupted: Arc<AtomicBool>,
+    terminated: Arc<AtomicBool>,
+}
+
+impl Default for Cancellation {
+    /// A flag set when rt receives SIGINT or SIGTERM, or once `terminate` is called.
+    fn default() -> Self {
+        Self {
+            interrupted: Arc::clone(&INTERRUPTED),
+            terminated: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl Cancellation {
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst) || self.terminated.load(Ordering::SeqCst)
+    }
+
+    /// Cancel the tasks sharing this flag and send SIGTERM to every running child.
+    pub fn terminate(&self) {
+        self.terminated.store(true, Ordering::SeqCst);
+        let children = CHILDREN
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for child in children {
+            child.signal(libc::SIGTERM);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RunningChild {
+    pid: libc::pid_t,
+    /// Whether the child leads its own process group, which the terminal's SIGINT does not reach.
+    own_group: bool,
+    signalled: Arc<AtomicBool>,
+}
+
+impl RunningChild {
+    fn signal(&self, signal: libc::c_int) {
+        self.signalled.store(true, Ordering::SeqCst);
+        // SAFETY: kill and killpg only send a signal; a process that already exited reports ESRCH,
+        // which is fine.
+        unsafe {
+            if self.own_group {
+                libc::killpg(self.pid, signal);
+            } else {
+                libc::kill(self.pid, signal);
+            }
+        }
+    }
+}
+
+/// Registration of a running child process, removed again on drop.
+pub struct ChildRegistration {
+    pid: libc::pid_t,
+    signalled: Arc<AtomicBool>,
+}
+
+impl ChildRegistration {
+    /// Whether rt sent the child a signal to stop it.
+    #[must_use]
+    pub fn signalled(&self) -> bool {
+        self.signalled.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ChildRegistration {
+    fn drop(&mut self) {
+        CHILDREN
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|child| child.pid != self.pid);
+    }
+}
+
+/// Register a spawned child so that signals received by rt are forwarded to it. A child spawned
+/// after rt was interrupted is terminated right away.
+#[must_use]
+pub fn register_child(pid: u32, own_group: bool) -> ChildRegistration {
+    let signalled = Arc::new(AtomicBool::new(false));
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return ChildRegistration { pid: 0, signalled };
+    };
+    let child = RunningChild {
+        pid,
+        own_group,
+        signalled: Arc::clone(&signalled),
+    };
+    if requested() {
+        child.signal(libc::SIGTERM);
+    }
+    CHILDREN
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(child);
+    ChildRegistration { pid, signalled }
+}
+
+/// Install the SIGINT and SIGTERM handler, once per process.
+///
+/// The first signal sets the cancellation flag and is forwarded to the running children; SIGINT
+/// only to those in their own process group, since the terminal already delivered it to the
+/// others. Any further signal kills all children.
+pub fn handle_signals() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let Ok(mut signals) = Signals::new([SIGINT, SIGTERM]) else {
+            return;
+        };
+        let received = AtomicUsize::new(0);
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                INTERRUPTED.store(true, Ordering::SeqCst);
+                let first = received.fetch_add(1, Ordering::SeqCst) == 0;
+                let children = CHILDREN
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone();
+                for child in children {
+                    if !first {
+                        child.signal(libc::SIGKILL);
+                    } else if child.own_group || signal != SIGINT {
+                        child.signal(signal);
+                    } else {
+                        child.signalled.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+        });
+    });
+}
@@ -0,0 +1,354 @@
+//! `JUnit` XML reports: each execution context writes its own report, which are merged into a
+//! single file with one testsuite per context.
+
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use roxmltree::{Document, Node};
+
+use crate::{
+    error::{RtError, RtResult},
+    history::{ContextResult, ContextStatus},
+};
+
+/// Report file of the execution context `hash` within `dir`.
+#[must_use]
+pub fn report_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join(format!("{}.xml", hash.replace('@', "-")))
+}
+
+/// Remove the report an earlier attempt left at `path`, if any.
+///
+/// # Errors
+///
+/// Returns an error if an existing report cannot be removed.
+pub fn clear_report(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    tests: u64,
+    failures: u64,
+    errors: u64,
+    skipped: u64,
+    time: f64,
+}
+
+impl Totals {
+    fn of(suite: Node<'_, '_>) -> Self {
+        let count = |name| {
+            suite
+                .attribute(name)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            tests: count("tests"),
+            failures: count("failures"),
+            errors: count("errors"),
+            skipped: count("skipped"),
+            time: suite
+                .attribute("time")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0.0),
+        }
+    }
+
+    fn add(&mut self, other: Self) {
+        self.tests += other.tests;
+        self.failures += other.failures;
+        self.errors += other.errors;
+        self.skipped += other.skipped;
+        self.time += other.time;
+    }
+}
+
+/// Merge the reports written to `reports_dir` by the contexts of `results` into `output`.
+///
+/// Every context gets a testsuite named after its venv, Python version and hash. Contexts without
+/// a readable report are represented by a single testcase, errored unless the command succeeded.
+///
+/// # Errors
+///
+/// Returns an error if the merged report cannot be written.
+pub fn merge(results: &[ContextResult], reports_dir: &Path, output: &Path) -> RtResult<()> {
+    let mut suites = String::new();
+    let mut totals = Totals::default();
+    for result in results {
+        let name = format!("{} py{} {}", result.name, result.python, result.hash);
+        let report = fs::read_to_string(report_path(reports_dir, &result.hash));
+        let problem = match &report {
+            Ok(xml) => match Document::parse(xml) {
+                Ok(document) => {
+                    let renamed = renamed_suites(&document, xml, &name);
+                    if !renamed.is_empty() {
+                        for (suite, suite_totals) in renamed {
+                            suites.push_str("  ");
+                            suites.push_str(&suite);
+                            suites.push('\n');
+                            totals.add(suite_totals);
+                        }
+                        continue;
+                    }
+                    "JUnit report contains no testsuite".to_string()
+                }
+                Err(err) => format!("invalid JUnit report: {err}"),
+            },
+            Err(_) => "no JUnit report was written".to_string(),
+        };
+        let (suite, suite_totals) = synthetic_suite(&name, result, &problem);
+        suites.push_str(&suite);
+        totals.add(suite_totals);
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"rt\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+        totals.tests, totals.failures, totals.errors, totals.skipped, totals.time
+    );
+    xml.push_str(&suites);
+    xml.push_str("</testsuites>\n");
+
+    fs::write(output, xml).map_err(|err| {
+        RtError::message(format!(
+            "error: failed to write JUnit report {}: {err}",
+            output.display()
+        ))
+    })
+}
+
+/// The outermost testsuites of `document`, copied from `xml` with their name replaced by `name`.
+/// A report holding several suites keeps their original names as a suffix.
+fn renamed_suites(document: &Document<'_>, xml: &str, name: &str) -> Vec<(String, Totals)> {
+    let suites: Vec<Node<'_, '_>> = document
+        .descendants()
+        .filter(|node| node.has_tag_name("testsuite"))
+        .filter(|node| {
+            !node
+                .ancestors()
+                .skip(1)
+                .any(|a| a.has_tag_name("testsuite"))
+        })
+        .collect();
+    let several = suites.len() > 1;
+
+    suites
+        .into_iter()
+        .map(|suite| {
+            let start = suite.range().start;
+            let mut text = xml[suite.range()].to_string();
+            let original = suite.attribute("name");
+            let suite_name = match original {
+                Some(original) if several => format!("{name} {original}"),
+                _ => name.to_string(),
+            };
+            let suite_name = escape(&suite_name);
+            match suite.attributes().find(|attr| attr.name() == "name") {
+                Some(attr) => {
+                    let range = attr.range_value();
+                    text.replace_range(range.start - start..range.end - start, &suite_name);
+                }
+                None => text.insert_str("<testsuite".len(), &format!(" name=\"{suite_name}\"")),
+            }
+            (text, Totals::of(suite))
+        })
+        .collect()
+}
+
+/// A one-testcase suite standing in for a context without a usable report: skipped, with the
+/// recorded reason, if the context never ran, errored if it failed.
+fn synthetic_suite(name: &str, result: &ContextResult, problem: &str) -> (String, Totals) {
+    let skipped = result.status == ContextStatus::Skipped;
+    let failed = result.exit_code != 0;
+    let totals = Totals {
+        tests: 1,
+        errors: u64::from(failed),
+        skipped: u64::from(skipped),
+        time: result.duration_secs,
+        ..Totals::default()
+    };
+    let mut suite = format!(
+        "  <testsuite name=\"{}\" tests=\"1\" failures=\"0\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        escape(name),
+        totals.errors,
+        totals.skipped,
+        totals.time
+    );
+    let testcase = format!(
+        "<testcase classname=\"rt\" name=\"{}\" time=\"{:.3}\"",
+        escape(&result.hash),
+        totals.time
+    );
+    if skipped {
+        let reason = result.skip_reason.as_deref().unwrap_or("not run");
+        let _ = writeln!(
+            suite,
+            "    {testcase}>\n      <skipped message=\"{}\"/>\n    </testcase>",
+            escape(reason)
+        );
+    } else if failed {
+        let outcome = match result.status {
+            ContextStatus::TimedOut => "command timed out".to_string(),
+            ContextStatus::Interrupted => "command was interrupted".to_string(),
+            _ => format!("command exited with status {}", result.exit_code),
+        };
+        let _ = writeln!(
+            suite,
+            "    {testcase}>\n      <error message=\"{}\">{}</error>\n    </testcase>",
+            escape(&format!("{outcome}; {problem}")),
+            escape(&result.command)
+        );
+    } else {
+        let _ = writeln!(suite, "    {testcase}/>");
+    }
+    suite.push_str("  </testsuite>\n");
+    (suite, totals)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use roxmltree::Document;
+
+    use super::{clear_report, merge, report_path};
+    use crate::history::{ContextResult, ContextStatus};
+
+    #[test]
+    fn merges_reports_and_errors_contexts_without_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = |hash: &str, exit_code| ContextResult {
+            hash: hash.to_string(),
+            name: "flask".to_string(),
+            python: "3.12".to_string(),
+            command: "pytest tests/ && echo <done>".to_string(),
+            status: if exit_code == 0 {
+                ContextStatus::Passed
+            } else {
+                ContextStatus::Failed
+            },
+            exit_code,
+            started_at: 0.0,
+            duration_secs: 1.5,
+            attempts: 1,
+            skip_reason: None,
+        };
+        std::fs::write(
+            report_path(dir.path(), "2b340ba@13515d1"),
+            r#"<?xml version="1.0" encoding="utf-8"?><testsuites name="pytest tests"><testsuite name="pytest" errors="0" failures="1" skipped="2" tests="5" time="0.250"><testcase classname="tests.test_views" name="test_index &amp; more" time="0.010"><failure message="boom">assert 1 == 2</failure></testcase></testsuite></testsuites>"#,
+        )
+        .unwrap();
+        let output = dir.path().join("merged.xml");
+        merge(
+            &[result("2b340ba@13515d1", 1), result("30a07c0@13515d1", 2)],
+            dir.path(),
+            &output,
+        )
+        .unwrap();
+
+        let xml = std::fs::read_to_string(&output).unwrap();
+        let document = Document::parse(&xml).unwrap();
+        let root = document.root_element();
+        assert_eq!(root.attribute("tests"), Some("6"));
+        assert_eq!(root.attribute("failures"), Some("1"));
+        assert_eq!(root.attribute("errors"), Some("1"));
+        assert_eq!(root.attribute("skipped"), Some("2"));
+        let suites: Vec<_> = root
+            .children()
+            .filter(|node| node.has_tag_name("testsuite"))
+            .collect();
+        assert_eq!(
+            suites[0].attribute("name"),
+            Some("flask py3.12 2b340ba@13515d1")
+        );
+        assert!(xml.contains("test_index &amp; more"));
+        assert_eq!(
+            suites[1].attribute("name"),
+            Some("flask py3.12 30a07c0@13515d1")
+        );
+        assert_eq!(suites[1].attribute("errors"), Some("1"));
+    }
+
+    #[test]
+    fn skips_with_the_recorded_reason_and_ignores_cleared_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = |hash: &str, status, skip_reason: Option<&str>| ContextResult {
+            hash: hash.to_string(),
+            name: "flask".to_string(),
+            python: "3.12".to_string(),
+            command: "pytest tests/".to_string(),
+            status,
+            exit_code: u8::from(status == ContextStatus::Failed),
+            started_at: 0.0,
+            duration_secs: 0.0,
+            attempts: 2,
+            skip_reason: skip_reason.map(str::to_string),
+        };
+        // Left behind by a first attempt; the second one crashed before writing its own.
+        let stale = report_path(dir.path(), "2b340ba@13515d1");
+        std::fs::write(
+            &stale,
+            r#"<testsuites><testsuite name="pytest" tests="1"><testcase classname="t" name="ok"/></testsuite></testsuites>"#,
+        )
+        .unwrap();
+        clear_report(&stale).unwrap();
+        clear_report(&stale).unwrap();
+        let output = dir.path().join("merged.xml");
+        merge(
+            &[
+                result("2b340ba@13515d1", ContextStatus::Failed, None),
+                result(
+                    "30a07c0@13515d1",
+                    ContextStatus::Skipped,
+                    Some("skipped after an earlier failure"),
+                ),
+            ],
+            dir.path(),
+            &output,
+        )
+        .unwrap();
+
+        let xml = std::fs::read_to_string(&output).unwrap();
+        let document = Document::parse(&xml).unwrap();
+        let root = document.root_element();
+        assert_eq!(root.attribute("tests"), Some("2"));
+        assert_eq!(root.attribute("errors"), Some("1"));
+        assert_eq!(root.attribute("skipped"), Some("1"));
+        let message = |tag: &str| {
+            document
+                .descendants()
+                .find(|node| node.has_tag_name(tag))
+                .and_then(|node| node.attribute("message"))
+                .map(str::to_string)
+        };
+        assert_eq!(
+            message("error").as_deref(),
+            Some("command exited with status 1; no JUnit report was written")
+        );
+        assert_eq!(
+            message("skipped").as_deref(),
+            Some("skipped after an earlier failure")
+        );
+    }
+}
@@ -15,19 +15,33 @@ mod config_provider;
 mod constants;
 mod display;
 mod error;
+mod history;
+mod interrupt;
+mod junit;
+mod manifest;
+mod offline;
 mod progress;
+mod query;
+mod riot_root;
+mod shard;
+mod specifier;
+mod step_log;
 mod ui;
 mod venv;
 
 use crate::{
-    config::{RepoConfig, RunConfig, Selector, load_rt_toml},
+    commands::clean::CleanLayers,
+    config::{RepoConfig, RunConfig, Selector, load_config_layers, merge_layers},
     error::{RtError, RtResult},
-    venv::{RiotVenv, load_context},
+    venv::{RiotVenv, apply_venv_scopes, load_context},
 };
 use clap::{Subcommand, ValueHint};
 use clap_complete::engine::ArgValueCompleter;
 use indexmap::IndexMap;
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 type DefaultConfigProvider = crate::config_provider::Pyo3ConfigProvider;
 
@@ -39,7 +53,7 @@ fn _rt(m: &Bound<'_, PyModule>) -> PyResult<()> {
 }
 
 #[pyfunction]
-fn cli_main(args: Vec<String>) -> u8 {
+fn cli_main(py: Python<'_>, args: Vec<String>) -> u8 {
     let current_dir = std::env::current_dir().ok();
     match CompleteEnv::with_factory(Cli::command).try_complete(args.clone(), current_dir.as_deref())
     {
@@ -48,13 +62,19 @@ fn cli_main(args: Vec<String>) -> u8 {
         Err(err) => err.exit(),
     }
 
-    match try_main(args) {
+    let exit_code = match try_main(args) {
         Ok(()) => 0,
         Err(err) => {
             err.report();
             err.exit_code()
         }
+    };
+    if interrupt::requested() {
+        // rt already handled the signal; drop the KeyboardInterrupt Python has pending for it.
+        let _ = py.check_signals();
+        return 130;
     }
+    exit_code
 }
 
 #[derive(Parser)]
@@ -69,6 +89,12 @@ struct Cli {
     pub file: Option<PathBuf>,
     #[arg(short, long, value_name = "PATH", add = ValueHint::DirPath)]
     pub riot_root: Option<PathBuf>,
+    /// Never access the network: uv only uses packages and interpreters already on this machine.
+    #[arg(long, global = true)]
+    pub offline: bool,
+    /// Report build and run progress as machine-readable events on stdout.
+    #[arg(long, global = true, value_name = "FORMAT")]
+    pub events: Option<progress::EventFormat>,
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -84,10 +110,22 @@ enum Commands {
         /// Output the selected environments as JSON instead of a hierarchy.
         #[arg(long = "json")]
         json: bool,
-        /// Optional regular expression to match venv names.
+        /// Only take group I of the selected execution contexts split into N groups (e.g. 2/4).
+        #[arg(long = "shard", value_name = "I/N", value_parser = shard::parse_shard)]
+        shard: Option<shard::Shard>,
+        /// How execution contexts are assigned to shards.
+        #[arg(
+            long = "shard-by",
+            value_name = "STRATEGY",
+            requires = "shard",
+            default_value = "hash"
+        )]
+        shard_by: shard::ShardBy,
+        /// Selector interpreted as execution context hash, venv hash, selector expression (e.g.
+        /// `name~'^flask' & python>=3.10 & pkg:flask^=2 & env:DD_X=1`), or name regex.
         #[arg(
-            value_name = "NAME_PATTERN",
-            add = ArgValueCompleter::new(completion::NameCompleter)
+            value_name = "PATTERN",
+            add = ArgValueCompleter::new(completion::SelectorCompleter)
         )]
         pattern: Option<String>,
         /// Filter venvs to specific Python versions.
@@ -119,6 +157,16 @@ enum Commands {
         /// Install the project in non-editable mode (copies built package instead of linking to source).
         #[arg(long = "no-editable")]
         no_editable: bool,
+        /// Stop starting steps after the first failure; `--fail-fast=terminate` also stops the
+        /// running ones.
+        #[arg(
+            long = "fail-fast",
+            value_name = "MODE",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "finish"
+        )]
+        fail_fast: Option<progress::FailFast>,
         /// Filter venvs to specific Python versions.
         #[arg(
             short = 'p',
@@ -127,7 +175,8 @@ enum Commands {
             add = ArgValueCompleter::new(completion::PythonCompleter)
         )]
         python: Option<Vec<String>>,
-        /// Selector interpreted as execution context hash, venv hash, or name regex (in that order).
+        /// Selector interpreted as execution context hash, venv hash, selector expression (e.g.
+        /// `name~'^flask' & python>=3.10 & pkg:flask^=2 & env:DD_X=1`), or name regex.
         #[arg(
             value_name = "PATTERN",
             required = true,
@@ -138,6 +187,30 @@ enum Commands {
         #[arg(short = 't', long = "test", value_name = "PYTEST_TARGET")]
         test: Option<String>,
     },
+    /// Resolve the packages of matched venvs into requirements lockfiles with `uv pip compile`.
+    Lock {
+        /// Re-resolve venvs that already have a lockfile.
+        #[arg(long = "force")]
+        force: bool,
+        /// Only verify that lockfiles exist and satisfy the riotfile constraints.
+        #[arg(long = "check", conflicts_with = "force")]
+        check: bool,
+        /// Filter venvs to specific Python versions.
+        #[arg(
+            short = 'p',
+            long = "python",
+            value_name = "PYTHON",
+            add = ArgValueCompleter::new(completion::PythonCompleter)
+        )]
+        python: Option<Vec<String>>,
+        /// Selector interpreted as execution context hash, venv hash, selector expression (e.g.
+        /// `name~'^flask' & python>=3.10 & pkg:flask^=2 & env:DD_X=1`), or name regex.
+        #[arg(
+            value_name = "PATTERN",
+            add = ArgValueCompleter::new(completion::SelectorCompleter)
+        )]
+        pattern: Option<String>,
+    },
     /// Build and execute the command for execution contexts matched by the selector.
     Run {
         /// Force reinstalling cached dependencies before running.
@@ -165,13 +238,60 @@ enum Commands {
             add = ArgValueCompleter::new(completion::PythonCompleter)
         )]
         python: Option<Vec<String>>,
-        /// Selector interpreted as execution context hash, venv hash, or name regex (in that order).
+        /// Selector interpreted as execution context hash, venv hash, selector expression (e.g.
+        /// `name~'^flask' & python>=3.10 & pkg:flask^=2 & env:DD_X=1`), or name regex.
         #[arg(
             value_name = "PATTERN",
-            required = true,
+            required_unless_present_any = ["affected", "failed"],
             add = ArgValueCompleter::new(completion::SelectorCompleter)
         )]
-        pattern: String,
+        pattern: Option<String>,
+        /// Only run execution contexts affected by the files changed since the git base.
+        #[arg(long = "affected")]
+        affected: bool,
+        /// Git base for --affected.
+        #[arg(long = "base", value_name = "REF", requires = "affected")]
+        base: Option<String>,
+        /// Only re-run the execution contexts that failed in the previous run, reusing its
+        /// command and arguments unless new ones are given.
+        #[arg(long = "failed")]
+        failed: bool,
+        /// Write a junit XML report with one testsuite per execution context.
+        #[arg(long = "junit-xml", value_name = "PATH")]
+        junit_xml: Option<PathBuf>,
+        /// Stop each execution context after this duration (e.g. 90s, 15m, 1h), killing all of
+        /// its processes.
+        #[arg(
+            long = "timeout",
+            value_name = "DURATION",
+            value_parser = config::parse_duration
+        )]
+        timeout: Option<Duration>,
+        /// Only take group I of the selected execution contexts split into N groups (e.g. 2/4).
+        #[arg(long = "shard", value_name = "I/N", value_parser = shard::parse_shard)]
+        shard: Option<shard::Shard>,
+        /// How execution contexts are assigned to shards.
+        #[arg(
+            long = "shard-by",
+            value_name = "STRATEGY",
+            requires = "shard",
+            default_value = "hash"
+        )]
+        shard_by: shard::ShardBy,
+        /// Re-execute a failed execution context up to N times; contexts passing only after a
+        /// retry are reported as flaky.
+        #[arg(long = "retries", value_name = "N")]
+        retries: Option<u32>,
+        /// Stop starting steps after the first failure; `--fail-fast=terminate` also stops the
+        /// running ones.
+        #[arg(
+            long = "fail-fast",
+            value_name = "MODE",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "finish"
+        )]
+        fail_fast: Option<progress::FailFast>,
         /// Filter to execution contexts whose pytest target prefix-matches this path.
         #[arg(short = 't', long = "test", value_name = "PYTEST_TARGET")]
         test: Option<String>,
@@ -215,8 +335,129 @@ enum Commands {
         #[arg(long = "force-reinstall")]
         force_reinstall: bool,
     },
-    /// Remove all cached virtual environments while keeping compiled requirements.
-    Clean,
+    /// Remove cached builds, optionally narrowed by selector, layer and age.
+    Clean {
+        /// Remove execution context venvs.
+        #[arg(long = "contexts")]
+        contexts: bool,
+        /// Remove dependency installs.
+        #[arg(long = "deps")]
+        deps: bool,
+        /// Remove development installs of the project.
+        #[arg(long = "dev-install")]
+        dev_install: bool,
+        /// Remove requirements lockfiles.
+        #[arg(long = "locks")]
+        locks: bool,
+        /// Only remove builds completed longer ago than this duration (e.g. 12h, 7d, 2w).
+        #[arg(
+            long = "older-than",
+            value_name = "DURATION",
+            value_parser = config::parse_duration
+        )]
+        older_than: Option<Duration>,
+        /// Filter venvs to specific Python versions.
+        #[arg(
+            short = 'p',
+            long = "python",
+            value_name = "PYTHON",
+            add = ArgValueCompleter::new(completion::PythonCompleter)
+        )]
+        python: Option<Vec<String>>,
+        /// Selector interpreted as execution context hash, venv hash, selector expression (e.g.
+        /// `name~'^flask' & python>=3.10 & pkg:flask^=2 & env:DD_X=1`), or name regex.
+        #[arg(
+            value_name = "PATTERN",
+            add = ArgValueCompleter::new(completion::SelectorCompleter)
+        )]
+        pattern: Option<String>,
+        /// Filter to execution contexts whose pytest target prefix-matches this path.
+        #[arg(short = 't', long = "test", value_name = "PYTEST_TARGET")]
+        test: Option<String>,
+    },
+    /// Print the execution contexts affected by the files changed since a git base.
+    Affected {
+        /// Git ref to diff against (from its merge base with HEAD).
+        #[arg(long = "base", value_name = "REF", default_value = commands::affected::DEFAULT_BASE)]
+        base: String,
+        /// Filter venvs to specific Python versions.
+        #[arg(
+            short = 'p',
+            long = "python",
+            value_name = "PYTHON",
+            add = ArgValueCompleter::new(completion::PythonCompleter)
+        )]
+        python: Option<Vec<String>>,
+        /// Selector interpreted as execution context hash, venv hash, selector expression (e.g.
+        /// `name~'^flask' & python>=3.10 & pkg:flask^=2 & env:DD_X=1`), or name regex.
+        #[arg(
+            value_name = "PATTERN",
+            add = ArgValueCompleter::new(completion::SelectorCompleter)
+        )]
+        pattern: Option<String>,
+    },
+    /// Show the outcome of recent runs, newest first.
+    History {
+        /// Number of runs to show.
+        #[arg(short = 'n', long = "limit", value_name = "N", default_value_t = 10)]
+        limit: usize,
+        /// Print the runs as JSON.
+        #[arg(long = "json")]
+        json: bool,
+    },
+    /// Show the latest build or run log of an execution context or venv.
+    Logs {
+        /// Execution or venv hash.
+        #[arg(
+            value_name = "HASH",
+            add = ArgValueCompleter::new(completion::HashCompleter)
+        )]
+        hash: String,
+        /// Only consider the logs of build steps.
+        #[arg(long = "build", conflicts_with = "run")]
+        build: bool,
+        /// Only consider the logs of run steps.
+        #[arg(long = "run")]
+        run: bool,
+        /// Show the log of this attempt of a retried step instead of the last one.
+        #[arg(long = "attempt", value_name = "N")]
+        attempt: Option<u32>,
+        /// Keep printing the log as it is written, until its step finishes.
+        #[arg(short = 'f', long = "follow")]
+        follow: bool,
+    },
+    /// Inspect the build cache under the riot root.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// Remove build directories and lockfiles no longer referenced by the riotfile.
+    Gc {
+        /// List unreferenced entries and their sizes without deleting them.
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Inspect rt configuration from the user config file and the project rt.toml.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print the effective configuration and where each value comes from.
+    Show,
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Report disk usage per build directory and layer, mapped back to riotfile venvs.
+    Du {
+        /// Print the report as JSON.
+        #[arg(long = "json")]
+        json: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -230,6 +471,7 @@ enum VscodeCommands {
 /// # Errors
 ///
 /// Returns an error if command execution fails.
+#[allow(clippy::too_many_lines)]
 fn run_command(
     riot_venvs: IndexMap<String, RiotVenv>,
     cli: Cli,
@@ -239,6 +481,8 @@ fn run_command(
         Commands::List {
             hash_only,
             json,
+            shard,
+            shard_by,
             pattern,
             python,
             test,
@@ -250,16 +494,24 @@ fn run_command(
                 ));
             }
             let selector = Selector::Generic {
-                python,
+                python: default_python(python, pattern.as_deref(), repo),
                 pattern,
                 test,
             };
-            commands::list::run(riot_venvs, repo, selector, hash_only, json)
+            commands::list::run(
+                riot_venvs,
+                repo,
+                selector,
+                shard.map(|shard| (shard, shard_by)),
+                hash_only,
+                json,
+            )
         }
         Commands::Describe { hash } => commands::describe::run(riot_venvs, repo, hash),
         Commands::Build {
             force_reinstall,
             no_editable,
+            fail_fast,
             pattern,
             python,
             test,
@@ -267,12 +519,29 @@ fn run_command(
             riot_venvs,
             repo,
             Selector::Generic {
-                python,
+                python: default_python(python, pattern.as_deref(), repo),
                 pattern,
                 test,
             },
             force_reinstall,
             no_editable,
+            fail_fast,
+        ),
+        Commands::Lock {
+            force,
+            check,
+            python,
+            pattern,
+        } => commands::lock::run(
+            riot_venvs,
+            repo,
+            Selector::Generic {
+                python: default_python(python, pattern.as_deref(), repo),
+                pattern,
+                test: None,
+            },
+            force,
+            check,
         ),
         Commands::Run {
             force_reinstall,
@@ -281,6 +550,15 @@ fn run_command(
             command_override,
             python,
             pattern,
+            affected,
+            base,
+            failed,
+            junit_xml,
+            timeout,
+            shard,
+            shard_by,
+            retries,
+            fail_fast,
             test,
             cmdargs,
         } => {
@@ -288,18 +566,26 @@ fn run_command(
                 command_override,
                 cmdargs,
                 action_label: "Execute".to_string(),
+                affected_base: affected
+                    .then(|| base.unwrap_or_else(|| commands::affected::DEFAULT_BASE.to_string())),
+                rerun_failed: failed,
+                shard: shard.map(|shard| (shard, shard_by)),
+                junit_xml,
+                timeout,
+                retries,
+                fail_fast,
             };
             commands::run::run(
                 riot_venvs,
                 repo,
                 Selector::Generic {
-                    python,
-                    pattern: Some(pattern),
+                    python: default_python(python, pattern.as_deref(), repo),
+                    pattern,
                     test,
                 },
                 force_reinstall,
                 no_editable,
-                parallel,
+                parallel.or(repo.parallel),
                 &run_config,
             )
         }
@@ -315,10 +601,85 @@ fn run_command(
             hash,
             force_reinstall,
         } => commands::switch::run(riot_venvs, repo, &hash, force_reinstall),
-        Commands::Clean => commands::clean::run(&repo.riot_root),
+        Commands::Clean {
+            contexts,
+            deps,
+            dev_install,
+            locks,
+            older_than,
+            python,
+            pattern,
+            test,
+        } => {
+            let selective = python.is_some() || pattern.is_some() || test.is_some();
+            let selector = selective.then_some(Selector::Generic {
+                python,
+                pattern,
+                test,
+            });
+            let layers = CleanLayers {
+                contexts,
+                deps,
+                dev_install,
+                locks,
+            };
+            commands::clean::run(riot_venvs, repo, selector, layers, older_than)
+        }
+        Commands::Affected {
+            base,
+            python,
+            pattern,
+        } => commands::affected::run(
+            riot_venvs,
+            repo,
+            Selector::Generic {
+                python: default_python(python, pattern.as_deref(), repo),
+                pattern,
+                test: None,
+            },
+            &base,
+        ),
+        Commands::History { limit, json } => commands::history::run(repo, limit, json),
+        Commands::Logs {
+            hash,
+            build,
+            run,
+            attempt,
+            follow,
+        } => {
+            let phase = if build {
+                Some(step_log::LogPhase::Build)
+            } else {
+                run.then_some(step_log::LogPhase::Run)
+            };
+            commands::logs::run(riot_venvs, repo, &hash, phase, attempt, follow)
+        }
+        Commands::Cache {
+            command: CacheCommands::Du { json },
+        } => commands::cache::disk_usage_report(&riot_venvs, repo, json),
+        Commands::Gc { dry_run } => commands::gc::run(&riot_venvs, repo, dry_run),
+        Commands::Config { .. } => {
+            unreachable!("rt config is dispatched before the riotfile is loaded")
+        }
     }
 }
 
+/// Python versions to select: `--python`, else the configured default, which does not narrow a
+/// venv or execution context hash.
+fn default_python(
+    python: Option<Vec<String>>,
+    pattern: Option<&str>,
+    repo: &RepoConfig,
+) -> Option<Vec<String>> {
+    python.or_else(|| {
+        if pattern.is_some_and(venv::is_hash) {
+            None
+        } else {
+            repo.python.clone()
+        }
+    })
+}
+
 fn locate_riotfile(riotfile_arg: Option<&PathBuf>) -> RtResult<PathBuf> {
     if let Some(path) = riotfile_arg {
         if !path.is_file() {
@@ -388,12 +749,21 @@ pub fn load_context_with_default_provider(
 fn try_main(args: Vec<String>) -> RtResult<()> {
     let cli = Cli::parse_from(args);
 
+    // Configuration can be inspected outside of a project, without evaluating the riotfile.
+    if matches!(cli.command, Commands::Config { .. }) {
+        let riotfile_path = locate_riotfile(cli.file.as_ref()).ok();
+        let layers = load_config_layers(riotfile_path.as_deref())?;
+        commands::config::show(&layers, cli.offline);
+        return Ok(());
+    }
+
     let riotfile_path = locate_riotfile(cli.file.as_ref())?;
     let riot_root = locate_riotroot(&riotfile_path, cli.riot_root.as_ref())?;
-    let riot_venvs = load_context_with_default_provider(&riotfile_path, Some(&riot_root))?;
+    let mut riot_venvs = load_context_with_default_provider(&riotfile_path, Some(&riot_root))?;
 
-    let (build_env, run_env) = load_rt_toml(&riotfile_path)?;
-    let repo_config = RepoConfig::load(riotfile_path, riot_root, build_env, run_env);
+    let rt_toml = merge_layers(load_config_layers(Some(&riotfile_path))?);
+    apply_venv_scopes(&mut riot_venvs, &rt_toml.venvs);
+    let repo_config = RepoConfig::load(riotfile_path, riot_root, rt_toml, cli.offline, cli.events);
 
     run_command(riot_venvs, cli, &repo_config)
 }
@@ -0,0 +1,87 @@
+//! Build fingerprints stored in each build directory's done marker.
+//!
+//! Every build step records the inputs it was built from. A step is only considered cached when
+//! the manifest on disk matches the one computed for the current inputs, so editing a lockfile,
+//! `[env.build]`, upgrading uv or switching interpreter patch releases triggers a rebuild.
+
+use std::{collections::HashMap, fmt::Write as _, fs, io, path::Path};
+
+use sha2::{Digest, Sha256};
+
+/// Ordered list of build inputs rendered as `key = "value"` lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildManifest {
+    entries: Vec<(String, String)>,
+}
+
+impl BuildManifest {
+    /// Start a manifest for the given build step kind.
+    #[must_use]
+    pub fn new(kind: &str) -> Self {
+        Self::default()
+            .entry("kind", kind)
+            .entry("rt", env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Record a single input.
+    #[must_use]
+    pub fn entry(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.entries.push((key.into(), value.into()));
+        self
+    }
+
+    /// Record every variable of an environment table, in a stable order.
+    #[must_use]
+    pub fn env_entries(mut self, prefix: &str, env: &HashMap<String, String>) -> Self {
+        let mut keys: Vec<&String> = env.keys().collect();
+        keys.sort();
+        for key in keys {
+            self.entries
+                .push((format!("{prefix}.{key}"), env[key].clone()));
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in &self.entries {
+            let quoted = serde_json::to_string(value).unwrap_or_default();
+            let _ = writeln!(out, "{key} = {quoted}");
+        }
+        out
+    }
+
+    /// Whether the marker at `path` was written from exactly these inputs.
+    #[must_use]
+    pub fn is_current(&self, path: &Path) -> bool {
+        fs::read_to_string(path).is_ok_and(|content| content == self.render())
+    }
+
+    /// Write the manifest as the done marker at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the marker cannot be written.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.render())
+    }
+}
+
+/// Short digest of a file's contents, used to chain manifests of dependent build steps.
+#[must_use]
+pub fn file_digest(path: &Path) -> String {
+    fs::read(path).map_or_else(|_| "missing".to_string(), |content| digest(&content))
+}
+
+/// Short hexadecimal SHA-256 digest of `content`.
+#[must_use]
+pub fn digest(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .take(8)
+        .fold(String::with_capacity(16), |mut out, byte| {
+            let _ = write!(out, "{byte:02x}");
+            out
+        })
+}
@@ -0,0 +1,152 @@
+//! Offline preflight: find the packages of a dependency set that uv cannot install from its
+//! cache, before any build step starts.
+
+use std::{io, process::Output};
+
+use indexmap::IndexSet;
+
+use crate::{
+    display::strip_ansi,
+    error::{RtError, RtResult},
+    specifier::normalize_name,
+};
+
+/// Phrases uv uses when an offline resolution needs a package it does not have.
+const NOT_IN_CACHE: &str = " was not found in the cache";
+const NO_VERSION: &str = "there is no version of ";
+
+/// Upper bound on dry-run resolutions for a single dependency set.
+const MAX_ROUNDS: usize = 64;
+
+/// Requirements of `requirements` that cannot be installed offline.
+///
+/// uv stops at the first unavailable package, so reported packages are dropped from the
+/// requirements and `dry_run` is called again until the rest resolves. Missing transitive
+/// dependencies are reported as-is since they cannot be dropped.
+///
+/// # Errors
+///
+/// Returns an error if uv cannot be spawned or a resolution fails for another reason.
+pub fn missing_from_cache(
+    requirements: &str,
+    mut dry_run: impl FnMut(&str) -> io::Result<Output>,
+) -> RtResult<Vec<String>> {
+    let mut remaining: Vec<&str> = requirements
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let mut missing = Vec::new();
+
+    for _ in 0..MAX_ROUNDS {
+        if remaining.is_empty() {
+            break;
+        }
+        let output = dry_run(&remaining.join("\n"))?;
+        if output.status.success() {
+            break;
+        }
+
+        let stderr = strip_ansi(&String::from_utf8_lossy(&output.stderr));
+        let reported = reported_missing(&stderr);
+        let before = remaining.len();
+        for package in &reported {
+            let name = normalize_name(requirement_name(package));
+            if let Some(pos) = remaining
+                .iter()
+                .position(|line| normalize_name(requirement_name(line)) == name)
+            {
+                missing.push(remaining.remove(pos).to_string());
+            }
+        }
+
+        if remaining.len() == before {
+            if reported.is_empty() {
+                let lines: Vec<&str> = stderr.lines().collect();
+                return Err(RtError::message(format!(
+                    "error: offline resolution failed:\n{}",
+                    lines[lines.len().saturating_sub(5)..].join("\n")
+                )));
+            }
+            missing.extend(reported);
+            break;
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Packages uv reports as unavailable in its output.
+fn reported_missing(stderr: &str) -> IndexSet<String> {
+    let trim = |token: &str| token.trim_matches([',', '.', ';', ':']).to_string();
+    let mut packages = IndexSet::new();
+
+    for (idx, _) in stderr.match_indices(NOT_IN_CACHE) {
+        if let Some(token) = stderr[..idx].split_whitespace().last() {
+            packages.insert(trim(token));
+        }
+    }
+    for (idx, _) in stderr.match_indices(NO_VERSION) {
+        if let Some(token) = stderr[idx + NO_VERSION.len()..].split_whitespace().next() {
+            packages.insert(trim(token));
+        }
+    }
+
+    packages.retain(|package| !package.is_empty());
+    packages
+}
+
+/// Distribution name of a requirement line such as `flask[async]>=2.0; python_version>"3"`.
+fn requirement_name(requirement: &str) -> &str {
+    requirement
+        .split(['=', '<', '>', '!', '~', ';', '@', ' '])
+        .next()
+        .unwrap_or(requirement)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::process::ExitStatusExt,
+        process::{ExitStatus, Output},
+    };
+
+    use super::missing_from_cache;
+
+    fn uv_output(stderr: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(if stderr.is_empty() { 0 } else { 1 << 8 }),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn drops_reported_packages_until_resolution_succeeds() {
+        let mut rounds = 0;
+        let requirements = "flask==3.0.0\npytest==8.0.0\nWerkzeug==3.0.1\n";
+        let missing = missing_from_cache(requirements, |reqs| {
+            rounds += 1;
+            Ok(uv_output(if reqs.contains("flask") {
+                "  ╰─▶ Because flask==3.0.0 was not found in the cache and you require \
+                 flask==3.0.0, we can conclude that your requirements are unsatisfiable."
+            } else if reqs.contains("Werkzeug") {
+                "  ╰─▶ Because there is no version of werkzeug==3.0.1 and you require \
+                 werkzeug==3.0.1, we can conclude that your requirements are unsatisfiable."
+            } else {
+                ""
+            }))
+        })
+        .unwrap();
+
+        assert_eq!(missing, ["flask==3.0.0", "Werkzeug==3.0.1"]);
+        assert_eq!(rounds, 3);
+    }
+
+    #[test]
+    fn reports_unrelated_failures() {
+        let err = missing_from_cache("flask\n", |_| Ok(uv_output("error: No interpreter found")))
+            .unwrap_err();
+        assert!(err.to_string().contains("No interpreter found"));
+    }
+}
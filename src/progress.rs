@@ -2,13 +2,21 @@ use std::{
     collections::HashMap,
     fmt::Display,
     io::{self, IsTerminal, Write},
-    sync::{Arc, Mutex},
-    time::Instant,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use rayon::{ThreadPoolBuilder, iter::IntoParallelIterator, iter::ParallelIterator};
+use serde::Serialize;
 
-use crate::display::{DisplayManager, StepStatus, strip_ansi};
+use crate::{
+    display::{DisplayManager, StepStatus, strip_ansi},
+    error::{RtError, Termination},
+    interrupt::{self, Cancellation},
+};
 
 /// Identifier for a task/step displayed to the user.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -26,17 +34,25 @@ impl StepId {
     }
 }
 
-/// Print a consistent error summary for task failures.
+/// Print a consistent error summary for task failures, including the tasks skipped after them.
 ///
 /// Returns `true` if any errors were reported.
-pub fn summarize_errors<E: Display>(errors: &[(String, E)], phase: &str) -> bool {
+pub fn summarize_errors<E: Display + TaskError>(errors: &[(String, E)], phase: &str) -> bool {
     if errors.is_empty() {
         return false;
     }
 
-    let count = errors.len();
+    let skipped = errors
+        .iter()
+        .filter(|(_, err)| err.step_status() == StepStatus::Skipped)
+        .count();
+    let count = errors.len() - skipped;
     let plural = if count == 1 { "" } else { "s" };
-    eprintln!("error summary: {count} failure{plural} during {phase}");
+    if skipped == 0 {
+        eprintln!("error summary: {count} failure{plural} during {phase}");
+    } else {
+        eprintln!("error summary: {count} failure{plural}, {skipped} skipped during {phase}");
+    }
     for (idx, (label, err)) in errors.iter().enumerate() {
         let num = idx + 1;
         eprintln!("  {num}. {label}: {err}");
@@ -44,11 +60,45 @@ pub fn summarize_errors<E: Display>(errors: &[(String, E)], phase: &str) -> bool
     true
 }
 
+/// Errors returned by tasks, deciding the status their failed step ends with.
+pub trait TaskError {
+    fn step_status(&self) -> StepStatus {
+        StepStatus::Failed
+    }
+
+    /// The error recorded for a task cancelled before it started.
+    fn interrupted() -> Self;
+
+    /// The error recorded for a task not started because an earlier one failed.
+    fn skipped() -> Self;
+}
+
+impl TaskError for RtError {
+    fn step_status(&self) -> StepStatus {
+        match self.termination() {
+            Some(Termination::TimedOut) => StepStatus::TimedOut,
+            Some(Termination::Interrupted) => StepStatus::Interrupted,
+            Some(Termination::Skipped) => StepStatus::Skipped,
+            None => StepStatus::Failed,
+        }
+    }
+
+    fn interrupted() -> Self {
+        Self::with_code(130, "interrupted").terminated(Termination::Interrupted)
+    }
+
+    fn skipped() -> Self {
+        Self::message("skipped after an earlier failure").terminated(Termination::Skipped)
+    }
+}
+
 /// Outcome reported by a task.
 #[derive(Clone, Copy, Debug)]
 pub enum StepOutcome {
     Done,
     Cached,
+    /// Succeeded only after being retried.
+    Flaky,
 }
 
 /// Context passed to tasks so they can emit output through the configured sink.
@@ -56,6 +106,8 @@ pub enum StepOutcome {
 pub struct StepContext {
     pub sink: Arc<dyn ProgressLogger>,
     pub step_id: StepId,
+    /// Set once rt is interrupted; long waits should give up when it is.
+    pub cancel: Cancellation,
 }
 
 /// Indicates how a logger wants command output to be delivered.
@@ -65,6 +117,33 @@ pub enum OutputPolicy {
     Inherit,
     /// Capture output so it can be multiplexed or formatted.
     Capture,
+    /// Capture output while it is echoed to the terminal as it arrives; stdin stays attached to
+    /// the terminal as with `Inherit`.
+    Tee,
+}
+
+/// Child process stream a chunk of command output was read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+}
+
+/// Machine-readable progress format selected with `--events`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum EventFormat {
+    /// One JSON object per line on stdout.
+    Ndjson,
 }
 
 /// Sink abstraction for progress reporting.
@@ -72,6 +151,16 @@ pub trait ProgressLogger: Send + Sync {
     fn register_step(&self, id: &StepId, label: &str);
     fn start(&self, id: &StepId);
     fn finish(&self, id: &StepId, status: StepStatus);
+    /// Report that the step is blocked, e.g. on a build lock held by another process.
+    ///
+    /// Calling `start` again marks the step as running once it can proceed.
+    fn wait(&self, _id: &StepId, _reason: &str) {}
+    /// Report that the step failed and runs again, as attempt `attempt` of at most `attempts`.
+    ///
+    /// Output appended afterwards belongs to the new attempt.
+    fn retry(&self, id: &StepId, attempt: u32, attempts: u32) {
+        self.append_output(id, format!("--- attempt {attempt}/{attempts} ---"));
+    }
     fn append_output(&self, id: &StepId, line: String);
     fn append_output_chunk(&self, id: &StepId, chunk: &[u8]) {
         if chunk.is_empty() {
@@ -80,6 +169,10 @@ pub trait ProgressLogger: Send + Sync {
         let text = String::from_utf8_lossy(chunk).into_owned();
         self.append_output(id, text);
     }
+    /// Like `append_output_chunk`, for loggers that distinguish stdout from stderr.
+    fn append_stream_chunk(&self, id: &StepId, _stream: OutputStream, chunk: &[u8]) {
+        self.append_output_chunk(id, chunk);
+    }
     fn flush_output(&self, _id: &StepId) {}
     fn output_policy(&self) -> OutputPolicy {
         OutputPolicy::Capture
@@ -110,8 +203,8 @@ impl StepGuard {
         self.finish_with(StepStatus::Cached);
     }
 
-    pub fn fail(mut self) {
-        self.finish_with(StepStatus::Failed);
+    pub fn finish(mut self, status: StepStatus) {
+        self.finish_with(status);
     }
 
     fn finish_with(&mut self, status: StepStatus) {
@@ -161,6 +254,11 @@ impl ProgressLogger for MultiplexedProgressLogger {
             .update_step_status(id.as_str(), StepStatus::Running);
     }
 
+    fn wait(&self, id: &StepId, _reason: &str) {
+        self.display
+            .update_step_status(id.as_str(), StepStatus::Waiting);
+    }
+
     fn finish(&self, id: &StepId, status: StepStatus) {
         self.display.update_step_status(id.as_str(), status);
     }
@@ -221,6 +319,21 @@ impl ProgressLogger for MultiplexedProgressLogger {
     }
 }
 
+/// Pick the progress sink for build-like commands: the event stream when `--events` is given,
+/// the multiplexed display when stderr is a terminal, plain output otherwise.
+#[must_use]
+pub fn default_sink(events: Option<EventFormat>) -> Arc<dyn ProgressLogger> {
+    if let Some(format) = events {
+        return event_sink(format);
+    }
+    if io::stderr().is_terminal()
+        && let Ok(logger) = MultiplexedProgressLogger::new()
+    {
+        return Arc::new(logger);
+    }
+    Arc::new(PlainProgressLogger::default())
+}
+
 /// Progress sink for plain, non-interactive output.
 ///
 /// When stderr is a TTY, subprocess output is inherited directly (preserving
@@ -270,12 +383,28 @@ impl ProgressLogger for PlainProgressLogger {
 
     fn start(&self, id: &StepId) {
         let mut steps = self.steps.lock().unwrap();
-        if let Some(state) = steps.get_mut(id) {
+        if let Some(state) = steps.get_mut(id)
+            && state.started_at.is_none()
+        {
             state.started_at = Some(Instant::now());
             eprintln!("==> {}", state.label);
         }
     }
 
+    fn wait(&self, id: &StepId, reason: &str) {
+        let steps = self.steps.lock().unwrap();
+        if let Some(state) = steps.get(id) {
+            eprintln!("    [waiting] {}: {reason}", state.label);
+        }
+    }
+
+    fn retry(&self, id: &StepId, attempt: u32, attempts: u32) {
+        let steps = self.steps.lock().unwrap();
+        if let Some(state) = steps.get(id) {
+            eprintln!("    [retry] {}: attempt {attempt}/{attempts}", state.label);
+        }
+    }
+
     fn finish(&self, id: &StepId, status: StepStatus) {
         let steps = self.steps.lock().unwrap();
         if let Some(state) = steps.get(id) {
@@ -286,7 +415,11 @@ impl ProgressLogger for PlainProgressLogger {
                 StepStatus::Done => "done",
                 StepStatus::Cached => "cached",
                 StepStatus::Failed => "FAILED",
-                StepStatus::Running | StepStatus::Pending => return,
+                StepStatus::TimedOut => "TIMED OUT",
+                StepStatus::Interrupted => "INTERRUPTED",
+                StepStatus::Skipped => "skipped",
+                StepStatus::Flaky => "FLAKY",
+                StepStatus::Running | StepStatus::Waiting | StepStatus::Pending => return,
             };
             match elapsed {
                 Some(dur) => eprintln!("    [{status_str}] {} ({dur})", state.label),
@@ -320,6 +453,18 @@ impl ProgressLogger for PlainProgressLogger {
         }
     }
 
+    fn append_stream_chunk(&self, id: &StepId, stream: OutputStream, chunk: &[u8]) {
+        if !self.is_tty || stream == OutputStream::Stderr {
+            self.append_output_chunk(id, chunk);
+            return;
+        }
+        if !chunk.is_empty() {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(chunk);
+            let _ = stdout.flush();
+        }
+    }
+
     fn output_policy(&self) -> OutputPolicy {
         if self.is_tty {
             OutputPolicy::Inherit
@@ -329,6 +474,193 @@ impl ProgressLogger for PlainProgressLogger {
     }
 }
 
+/// Progress sink emitting the requested machine-readable event format.
+#[must_use]
+pub fn event_sink(format: EventFormat) -> Arc<dyn ProgressLogger> {
+    match format {
+        EventFormat::Ndjson => Arc::new(NdjsonProgressLogger::default()),
+    }
+}
+
+/// Progress sink writing one JSON event per line to stdout, for editor integrations and other
+/// tools driving rt.
+///
+/// Every event carries the step id, its label, the step status and a Unix timestamp; output
+/// events add the stream the chunk was read from, with ANSI escape codes stripped.
+pub struct NdjsonProgressLogger {
+    steps: Mutex<HashMap<StepId, NdjsonStepState>>,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Default for NdjsonProgressLogger {
+    fn default() -> Self {
+        Self::to_writer(io::stdout())
+    }
+}
+
+struct NdjsonStepState {
+    label: String,
+    status: StepStatus,
+    started_at: Option<Instant>,
+}
+
+#[derive(Serialize)]
+struct Event<'a> {
+    #[serde(rename = "event")]
+    kind: &'static str,
+    step: &'a str,
+    label: &'a str,
+    status: &'static str,
+    /// Seconds since the Unix epoch.
+    timestamp: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elapsed_secs: Option<f64>,
+    #[serde(flatten)]
+    detail: EventDetail<'a>,
+}
+
+/// Fields specific to some kinds of events.
+#[derive(Default, Serialize)]
+struct EventDetail<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,
+}
+
+impl NdjsonProgressLogger {
+    /// Write the events to `out` instead of stdout.
+    #[must_use]
+    pub fn to_writer(out: impl Write + Send + 'static) -> Self {
+        Self {
+            steps: Mutex::new(HashMap::new()),
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    /// Move step `id` to `status`, if given, and emit an event describing it.
+    fn emit(
+        &self,
+        id: &StepId,
+        kind: &'static str,
+        status: Option<StepStatus>,
+        detail: EventDetail<'_>,
+    ) {
+        let mut steps = self.steps.lock().unwrap();
+        let state = steps.entry(id.clone()).or_insert_with(|| NdjsonStepState {
+            label: id.as_str().to_string(),
+            status: StepStatus::Pending,
+            started_at: None,
+        });
+        if let Some(status) = status {
+            state.status = status;
+        }
+        if state.status == StepStatus::Running {
+            state.started_at.get_or_insert_with(Instant::now);
+        }
+        let finished = matches!(
+            state.status,
+            StepStatus::Done | StepStatus::Cached | StepStatus::Flaky
+        ) || state.status.is_failure();
+        let event = Event {
+            kind,
+            step: id.as_str(),
+            label: &state.label,
+            status: state.status.label(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs_f64(),
+            elapsed_secs: state
+                .started_at
+                .filter(|_| finished)
+                .map(|started_at| started_at.elapsed().as_secs_f64()),
+            detail,
+        };
+        let line = serde_json::to_string(&event);
+        drop(steps);
+        if let Ok(line) = line {
+            let mut out = self.out.lock().unwrap();
+            let _ = writeln!(out, "{line}");
+            let _ = out.flush();
+        }
+    }
+}
+
+impl ProgressLogger for NdjsonProgressLogger {
+    fn register_step(&self, id: &StepId, label: &str) {
+        // Steps registered up front are registered again when their task runs.
+        let mut steps = self.steps.lock().unwrap();
+        if steps.contains_key(id) {
+            return;
+        }
+        steps.insert(
+            id.clone(),
+            NdjsonStepState {
+                label: label.to_string(),
+                status: StepStatus::Pending,
+                started_at: None,
+            },
+        );
+        drop(steps);
+        self.emit(id, "register", None, EventDetail::default());
+    }
+
+    fn start(&self, id: &StepId) {
+        self.emit(
+            id,
+            "start",
+            Some(StepStatus::Running),
+            EventDetail::default(),
+        );
+    }
+
+    fn wait(&self, id: &StepId, reason: &str) {
+        let detail = EventDetail {
+            reason: Some(reason),
+            ..EventDetail::default()
+        };
+        self.emit(id, "wait", Some(StepStatus::Waiting), detail);
+    }
+
+    fn retry(&self, id: &StepId, attempt: u32, attempts: u32) {
+        let reason = format!("attempt {attempt}/{attempts}");
+        let detail = EventDetail {
+            reason: Some(&reason),
+            ..EventDetail::default()
+        };
+        self.emit(id, "retry", None, detail);
+    }
+
+    fn finish(&self, id: &StepId, status: StepStatus) {
+        self.emit(id, "finish", Some(status), EventDetail::default());
+    }
+
+    fn append_output(&self, id: &StepId, line: String) {
+        let text = strip_ansi(&line);
+        let detail = EventDetail {
+            text: Some(&text),
+            ..EventDetail::default()
+        };
+        self.emit(id, "output", None, detail);
+    }
+
+    fn append_stream_chunk(&self, id: &StepId, stream: OutputStream, chunk: &[u8]) {
+        if chunk.is_empty() {
+            return;
+        }
+        let text = strip_ansi(&String::from_utf8_lossy(chunk));
+        let detail = EventDetail {
+            stream: Some(stream.label()),
+            text: Some(&text),
+            ..EventDetail::default()
+        };
+        self.emit(id, "output", None, detail);
+    }
+}
+
 /// A unit of work to be executed by the `TaskRunner`.
 pub struct Task<'a, E> {
     pub id: StepId,
@@ -349,10 +681,20 @@ impl<'a, E> Task<'a, E> {
     }
 }
 
+/// What `--fail-fast` does with the tasks still running after the first failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum FailFast {
+    /// Let running tasks finish.
+    Finish,
+    /// Terminate running tasks, along with the commands they started.
+    Terminate,
+}
+
 /// Executes a batch of tasks, optionally in parallel, while reporting progress to the configured sink.
 pub struct TaskRunner {
     sink: Arc<dyn ProgressLogger>,
     parallelism: Option<usize>,
+    fail_fast: Option<FailFast>,
 }
 
 impl TaskRunner {
@@ -361,6 +703,7 @@ impl TaskRunner {
         Self {
             sink,
             parallelism: None,
+            fail_fast: None,
         }
     }
 
@@ -370,8 +713,20 @@ impl TaskRunner {
         self
     }
 
+    /// Stop starting tasks after the first failure.
+    #[must_use]
+    pub const fn with_fail_fast(mut self, fail_fast: Option<FailFast>) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
     /// Run all provided tasks and collect failures.
     ///
+    /// Once rt is interrupted, tasks that have not started yet are marked as interrupted instead
+    /// of being run, as are the ones that fail afterwards. After a failure in fail-fast mode the
+    /// remaining tasks are skipped, and running ones terminated if requested. Skipped and
+    /// interrupted tasks are returned along with the failures.
+    ///
     /// # Errors
     ///
     /// Returns an error if the Rayon thread pool cannot be constructed.
@@ -380,21 +735,35 @@ impl TaskRunner {
         tasks: Vec<Task<'a, E>>,
     ) -> Result<Vec<(String, E)>, rayon::ThreadPoolBuildError>
     where
-        E: Send + 'a,
+        E: TaskError + Send + 'a,
     {
         // Ensure steps are visible before work begins.
         for task in &tasks {
             self.sink.register_step(&task.id, &task.label);
         }
 
+        interrupt::handle_signals();
         let sink = Arc::clone(&self.sink);
+        let fail_fast = self.fail_fast;
+        let cancel = Cancellation::default();
+        let stopped = AtomicBool::new(false);
+        let stopped = &stopped;
 
         let run_one = move |task: Task<'a, E>| -> Option<(String, E)> {
+            if interrupt::requested() {
+                sink.finish(&task.id, StepStatus::Interrupted);
+                return Some((task.label, E::interrupted()));
+            }
+            if stopped.load(Ordering::SeqCst) {
+                sink.finish(&task.id, StepStatus::Skipped);
+                return Some((task.label, E::skipped()));
+            }
             sink.start(&task.id);
             let guard = StepGuard::new(Arc::clone(&sink), task.id.clone());
             let result = (task.exec)(StepContext {
                 sink: Arc::clone(&sink),
                 step_id: task.id.clone(),
+                cancel: cancel.clone(),
             });
 
             match result {
@@ -406,8 +775,22 @@ impl TaskRunner {
                     guard.cached();
                     None
                 }
+                Ok(StepOutcome::Flaky) => {
+                    guard.finish(StepStatus::Flaky);
+                    None
+                }
+                Err(err) if cancel.is_cancelled() => {
+                    guard.finish(StepStatus::Interrupted);
+                    Some((task.label, err))
+                }
                 Err(err) => {
-                    guard.fail();
+                    guard.finish(err.step_status());
+                    if let Some(mode) = fail_fast
+                        && !stopped.swap(true, Ordering::SeqCst)
+                        && mode == FailFast::Terminate
+                    {
+                        cancel.terminate();
+                    }
                     Some((task.label, err))
                 }
             }
@@ -426,4 +809,97 @@ impl TaskRunner {
             None => Ok(tasks.into_iter().filter_map(run_one).collect::<Vec<_>>()),
         }
     }
+
+    /// Finish `tasks` without running them, because an earlier batch failed or rt was
+    /// interrupted, and return the errors recorded for them.
+    pub fn skip<E: TaskError>(&self, tasks: Vec<Task<'_, E>>) -> Vec<(String, E)> {
+        tasks
+            .into_iter()
+            .map(|task| {
+                if interrupt::requested() {
+                    self.sink.finish(&task.id, StepStatus::Interrupted);
+                    (task.label, E::interrupted())
+                } else {
+                    self.sink.finish(&task.id, StepStatus::Skipped);
+                    (task.label, E::skipped())
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicBool, Ordering},
+        },
+    };
+
+    use super::{
+        FailFast, NdjsonProgressLogger, PlainProgressLogger, ProgressLogger, StepId, StepOutcome,
+        Task, TaskRunner,
+    };
+    use crate::error::RtError;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ndjson_registers_pre_registered_steps_once() {
+        let buffer = Buffer::default();
+        let sink = Arc::new(NdjsonProgressLogger::to_writer(buffer.clone()));
+        let id = StepId::new("create execution context 2b340ba@13515d1");
+        sink.register_step(&id, id.as_str());
+
+        let tasks = vec![Task::new(id.clone(), id.as_str(), |_| {
+            Ok::<_, RtError>(StepOutcome::Done)
+        })];
+        let errors = TaskRunner::new(sink).run(tasks).unwrap();
+        assert!(errors.is_empty());
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|event| event["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds.iter().filter(|kind| **kind == "register").count(), 1);
+        assert_eq!(events.last().unwrap()["status"], "done");
+    }
+
+    #[test]
+    fn fail_fast_skips_tasks_after_the_first_failure() {
+        let ran = AtomicBool::new(false);
+        let tasks = vec![
+            Task::new(StepId::new("a"), "a", |_| Err(RtError::message("boom"))),
+            Task::new(StepId::new("b"), "b", |_| {
+                ran.store(true, Ordering::SeqCst);
+                Ok(StepOutcome::Done)
+            }),
+        ];
+        let errors = TaskRunner::new(Arc::new(PlainProgressLogger::default()))
+            .with_fail_fast(Some(FailFast::Finish))
+            .run(tasks)
+            .unwrap();
+
+        let labels: Vec<&str> = errors.iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(labels, ["a", "b"]);
+        assert!(!ran.load(Ordering::SeqCst));
+    }
 }
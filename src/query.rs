@@ -0,0 +1,390 @@
+//! Boolean selector expressions over venv name, Python version, packages and env, such as
+//! `name~'^flask' & python>=3.10 & pkg:flask^=2 & env:DD_X=1 & !name~snapshot`.
+//!
+//! Terms combine with `&`, `|`, `!` and parentheses. Each term is a field, an optional operator
+//! and a value:
+//!
+//! - `name`, `python`, `hash`: the venv name, its Python version, and the venv or execution
+//!   context hash.
+//! - `pkg:<NAME>`: the version of a package, pinned by the lockfile or else taken from the
+//!   riotfile constraint. `~` matches the riotfile constraint itself.
+//! - `env:<NAME>`: an environment variable of the execution context.
+//!
+//! Operators are `=`, `!=`, `<`, `<=`, `>`, `>=` (compared as versions where both sides parse),
+//! `^=` (prefix, by release segment for versions) and `~` (regex search). A field without an
+//! operator tests that the package or variable is present.
+
+use std::cmp::Ordering;
+
+use fancy_regex::Regex;
+
+use crate::{
+    error::{RtError, RtResult},
+    specifier::{Version, normalize_name},
+    venv::{ExecutionContext, RiotVenv},
+};
+
+/// Fields a selector expression can start with.
+pub const FIELDS: &[&str] = &["name", "python", "hash", "pkg:", "env:"];
+
+#[derive(Debug)]
+pub enum Query {
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+    Not(Box<Self>),
+    Term(Term),
+}
+
+#[derive(Debug)]
+pub struct Term {
+    field: Field,
+    test: Option<(Op, Value)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Name,
+    Python,
+    Hash,
+    Pkg(String),
+    Env(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Prefix,
+    Regex,
+}
+
+#[derive(Debug)]
+struct Value {
+    text: String,
+    regex: Option<Regex>,
+}
+
+/// Whether a selector should be parsed as an expression rather than used as a name regex: it
+/// starts with a known field followed by an operator, or with `pkg:`/`env:`.
+#[must_use]
+pub fn is_query(selector: &str) -> bool {
+    let rest = selector.trim_start_matches(|c: char| c == '!' || c == '(' || c.is_whitespace());
+    FIELDS.iter().any(|field| {
+        rest.strip_prefix(field).is_some_and(|after| {
+            field.ends_with(':')
+                || after
+                    .trim_start()
+                    .starts_with(['~', '=', '!', '<', '>', '^'])
+        })
+    })
+}
+
+impl Query {
+    /// Parse a selector expression.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first syntax problem and its position.
+    pub fn parse(input: &str) -> RtResult<Self> {
+        let mut parser = Parser { input, pos: 0 };
+        let query = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.pos < input.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(query)
+    }
+
+    /// Keep the execution contexts of `venv` matching the expression; returns whether the venv
+    /// is selected. Venvs without execution contexts are matched on their own fields.
+    pub fn select(&self, venv: &mut RiotVenv) -> bool {
+        if venv.execution_contexts.is_empty() {
+            return self.matches(venv, None);
+        }
+        let contexts = std::mem::take(&mut venv.execution_contexts);
+        venv.execution_contexts = contexts
+            .into_iter()
+            .filter(|ctx| self.matches(venv, Some(ctx)))
+            .collect();
+        !venv.execution_contexts.is_empty()
+    }
+
+    fn matches(&self, venv: &RiotVenv, ctx: Option<&ExecutionContext>) -> bool {
+        match self {
+            Self::And(left, right) => left.matches(venv, ctx) && right.matches(venv, ctx),
+            Self::Or(left, right) => left.matches(venv, ctx) || right.matches(venv, ctx),
+            Self::Not(inner) => !inner.matches(venv, ctx),
+            Self::Term(term) => term.matches(venv, ctx),
+        }
+    }
+}
+
+impl Term {
+    fn matches(&self, venv: &RiotVenv, ctx: Option<&ExecutionContext>) -> bool {
+        match &self.field {
+            Field::Name => self.test_value(&venv.name),
+            Field::Python => self.test_value(&venv.python),
+            Field::Hash => {
+                self.test_value(&venv.hash) || ctx.is_some_and(|ctx| self.test_value(&ctx.hash))
+            }
+            Field::Pkg(name) => {
+                let name = normalize_name(name);
+                let installed = venv.installed_pkgs();
+                let constraint = installed
+                    .iter()
+                    .find(|(pkg, _)| normalize_name(pkg) == name)
+                    .map(|(_, constraint)| constraint.as_str());
+                let pinned = venv
+                    .resolved_pkgs
+                    .iter()
+                    .find(|(pkg, _)| normalize_name(pkg) == name)
+                    .map(|(_, version)| version.as_str());
+                match &self.test {
+                    None => constraint.is_some() || pinned.is_some(),
+                    Some((Op::Regex, _)) => constraint.is_some_and(|c| self.test_value(c)),
+                    Some(_) => pinned
+                        .or_else(|| constraint.map(constraint_version))
+                        .is_some_and(|version| self.test_value(version)),
+                }
+            }
+            Field::Env(name) => ctx
+                .and_then(|ctx| ctx.env.get(name))
+                .is_some_and(|value| self.test_value(value)),
+        }
+    }
+
+    fn test_value(&self, actual: &str) -> bool {
+        let Some((op, expected)) = &self.test else {
+            return true;
+        };
+        let ordering = || match (Version::parse(actual), Version::parse(&expected.text)) {
+            (Some(actual), Some(expected)) => Some(actual.cmp(&expected)),
+            _ => None,
+        };
+        match op {
+            Op::Eq => ordering().map_or(actual == expected.text, Ordering::is_eq),
+            Op::Ne => !ordering().map_or(actual == expected.text, Ordering::is_eq),
+            Op::Lt => ordering().is_some_and(Ordering::is_lt),
+            Op::Le => ordering().is_some_and(Ordering::is_le),
+            Op::Gt => ordering().is_some_and(Ordering::is_gt),
+            Op::Ge => ordering().is_some_and(Ordering::is_ge),
+            Op::Prefix => has_prefix(actual, &expected.text),
+            Op::Regex => expected
+                .regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(actual).unwrap_or(false)),
+        }
+    }
+}
+
+/// `actual` starts with `prefix`; for versions only at a segment boundary, so `2` matches
+/// `2.0.1` but not `20.1`.
+fn has_prefix(actual: &str, prefix: &str) -> bool {
+    actual.strip_prefix(prefix).is_some_and(|rest| {
+        rest.is_empty()
+            || !prefix.ends_with(|c: char| c.is_ascii_digit())
+            || !rest.starts_with(|c: char| c.is_ascii_digit())
+    })
+}
+
+/// Version named by a riotfile constraint such as `~=2.0` or `>=1.4,<2`.
+fn constraint_version(constraint: &str) -> &str {
+    let first = constraint.split(',').next().unwrap_or(constraint);
+    first
+        .trim()
+        .trim_start_matches(['=', '~', '<', '>', '!'])
+        .trim()
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> RtError {
+        RtError::message(format!(
+            "error: invalid selector expression: {message} at column {}\n  {}\n  {}^",
+            self.pos + 1,
+            self.input,
+            " ".repeat(self.input[..self.pos].chars().count())
+        ))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> RtResult<Query> {
+        let mut query = self.parse_and()?;
+        while self.eat("|") {
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> RtResult<Query> {
+        let mut query = self.parse_unary()?;
+        while self.eat("&") {
+            query = Query::And(Box::new(query), Box::new(self.parse_unary()?));
+        }
+        Ok(query)
+    }
+
+    fn parse_unary(&mut self) -> RtResult<Query> {
+        if self.eat("!") {
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat("(") {
+            let query = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(self.error("expected `)`"));
+            }
+            return Ok(query);
+        }
+        self.parse_term().map(Query::Term)
+    }
+
+    fn parse_term(&mut self) -> RtResult<Term> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| {
+                !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '[' | ']'))
+            })
+            .unwrap_or(rest.len());
+        let ident = &rest[..len];
+        let field = match ident.split_once(':') {
+            None if ident == "name" => Field::Name,
+            None if ident == "python" => Field::Python,
+            None if ident == "hash" => Field::Hash,
+            Some(("pkg", name)) if !name.is_empty() => Field::Pkg(name.to_string()),
+            Some(("env", name)) if !name.is_empty() => Field::Env(name.to_string()),
+            _ => {
+                return Err(
+                    self.error("expected a field (name, python, hash, pkg:<NAME> or env:<NAME>)")
+                );
+            }
+        };
+        self.pos += len;
+
+        let Some(op) = self.parse_op() else {
+            if matches!(field, Field::Pkg(_) | Field::Env(_)) {
+                return Ok(Term { field, test: None });
+            }
+            return Err(self.error("expected an operator (=, !=, <, <=, >, >=, ^= or ~)"));
+        };
+        if matches!(op, Op::Lt | Op::Le | Op::Gt | Op::Ge)
+            && matches!(field, Field::Name | Field::Hash | Field::Env(_))
+        {
+            return Err(self.error("ordering operators only apply to python and pkg"));
+        }
+        let value = self.parse_value()?;
+        let regex = if op == Op::Regex {
+            Some(Regex::new(&value).map_err(|err| self.error(&format!("invalid regex ({err})")))?)
+        } else {
+            None
+        };
+
+        Ok(Term {
+            field,
+            test: Some((op, Value { text: value, regex })),
+        })
+    }
+
+    fn parse_op(&mut self) -> Option<Op> {
+        self.skip_whitespace();
+        let op = [
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("^=", Op::Prefix),
+            ("==", Op::Eq),
+            ("=", Op::Eq),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+            ("~", Op::Regex),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.rest().starts_with(token))?;
+        self.pos += op.0.len();
+        Some(op.1)
+    }
+
+    fn parse_value(&mut self) -> RtResult<String> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '\'' || *c == '"') {
+            let Some(end) = rest[1..].find(quote) else {
+                return Err(self.error("unterminated quoted value"));
+            };
+            let value = rest[1..=end].to_string();
+            self.pos += end + 2;
+            return Ok(value);
+        }
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '&' | '|' | '(' | ')'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += len;
+        Ok(rest[..len].to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, has_prefix, is_query};
+
+    #[test]
+    fn detects_expressions_and_leaves_name_patterns_alone() {
+        assert!(is_query("name~'^flask'"));
+        assert!(is_query("!pkg:flask"));
+        assert!(is_query("(python>=3.10 | env:X=1)"));
+        assert!(!is_query("flask|django"));
+        assert!(!is_query("^name"));
+        assert!(!is_query("python"));
+    }
+
+    #[test]
+    fn parses_precedence_and_reports_positions() {
+        let query =
+            Query::parse("name~'^flask' & python>=3.10 | !env:DD_X=1 & pkg:flask^=2").unwrap();
+        assert!(matches!(query, Query::Or(_, _)));
+
+        let err = Query::parse("name~flask & pyton>=3").unwrap_err();
+        assert!(err.to_string().contains("expected a field"));
+        assert!(err.to_string().contains("column 14"));
+        assert!(Query::parse("env:X<1").is_err());
+        assert!(Query::parse("(name=a").is_err());
+    }
+
+    #[test]
+    fn version_prefixes_stop_at_segment_boundaries() {
+        assert!(has_prefix("2.0.1", "2"));
+        assert!(has_prefix("2", "2"));
+        assert!(!has_prefix("20.1", "2"));
+        assert!(!has_prefix("3.12", "3.1"));
+        assert!(has_prefix("flask-async", "flask"));
+    }
+}
@@ -0,0 +1,671 @@
+//! Layout of the riot root: which build directories and lockfiles exist on disk, how large they
+//! are, and which of them the current riotfile still references.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, TryLockError},
+    io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use indexmap::IndexMap;
+
+use crate::{
+    commands::build::{deps_key, get_deps_install_path, get_dev_install_path},
+    constants::{
+        IN_USE_MARKER, LOCKS_DIR, REQUIREMENTS_DIR, VENV_DEPS_DIR, VENV_PREFIX, VENV_SELF_DIR,
+    },
+    error::{RtError, RtResult},
+    venv::{ExecutionContext, RiotVenv, lockfile_path, venv_path},
+};
+
+/// Interval at which a build lock held by another process is retried.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Kind of entry found under the riot root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntryKind {
+    ExecutionContext,
+    Deps,
+    DevInstall,
+    Lockfile,
+    /// Leftover of an interrupted build, or a replaced build no process runs from any more; safe
+    /// to delete.
+    Staging,
+}
+
+impl EntryKind {
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::ExecutionContext => "context",
+            Self::Deps => "deps",
+            Self::DevInstall => "dev install",
+            Self::Lockfile => "lockfile",
+            Self::Staging => "staging",
+        }
+    }
+}
+
+/// A build directory or lockfile under the riot root.
+#[derive(Debug, Clone)]
+pub struct RootEntry {
+    pub kind: EntryKind,
+    pub path: PathBuf,
+}
+
+/// List every execution context, deps, dev install directory and lockfile under `riot_root`.
+///
+/// Staging directories left behind by interrupted builds and retired builds are reported as
+/// [`EntryKind::Staging`], except those of a build still in progress in another process and
+/// those a process still runs from.
+///
+/// # Errors
+///
+/// Returns an error if an existing directory cannot be read.
+pub fn scan(riot_root: &Path) -> RtResult<Vec<RootEntry>> {
+    let mut entries = Vec::new();
+
+    let build_dirs = [
+        (riot_root.to_path_buf(), VENV_PREFIX),
+        (riot_root.join(VENV_DEPS_DIR), "deps_"),
+        (riot_root.join(VENV_SELF_DIR), "self_py"),
+    ];
+    for (dir, prefix) in &build_dirs {
+        for (name, path) in read_dir_entries(dir, true)? {
+            let Some(target_name) = staged_target_name(&name) else {
+                continue;
+            };
+            if name.starts_with(prefix)
+                && !is_locked(riot_root, &dir.join(target_name))
+                && !in_use(&path)
+            {
+                entries.push(RootEntry {
+                    kind: EntryKind::Staging,
+                    path,
+                });
+            }
+        }
+    }
+
+    for (name, path) in read_dir_entries(riot_root, true)? {
+        if staged_target_name(&name).is_some() {
+            continue;
+        }
+        if name.starts_with(VENV_PREFIX) && name != VENV_DEPS_DIR && name != VENV_SELF_DIR {
+            entries.push(RootEntry {
+                kind: EntryKind::ExecutionContext,
+                path,
+            });
+        }
+    }
+
+    for (name, path) in read_dir_entries(&riot_root.join(VENV_DEPS_DIR), true)? {
+        if name.starts_with("deps_") && staged_target_name(&name).is_none() {
+            entries.push(RootEntry {
+                kind: EntryKind::Deps,
+                path,
+            });
+        }
+    }
+
+    for (name, path) in read_dir_entries(&riot_root.join(VENV_SELF_DIR), true)? {
+        if name.starts_with("self_py") && staged_target_name(&name).is_none() {
+            entries.push(RootEntry {
+                kind: EntryKind::DevInstall,
+                path,
+            });
+        }
+    }
+
+    for (name, path) in read_dir_entries(&riot_root.join(REQUIREMENTS_DIR), false)? {
+        if Path::new(&name).extension().is_some_and(|ext| ext == "txt") {
+            entries.push(RootEntry {
+                kind: EntryKind::Lockfile,
+                path,
+            });
+        }
+    }
+
+    entries.sort_by(|left, right| (left.kind, &left.path).cmp(&(right.kind, &right.path)));
+    Ok(entries)
+}
+
+fn read_dir_entries(dir: &Path, dirs: bool) -> RtResult<Vec<(String, PathBuf)>> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(RtError::message(format!(
+                "error: failed to read {}: {err}",
+                dir.display()
+            )));
+        }
+    };
+
+    let mut found = Vec::new();
+    for entry in read_dir {
+        let entry = entry.map_err(|err| {
+            RtError::message(format!(
+                "error: failed to read entry of {}: {err}",
+                dir.display()
+            ))
+        })?;
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() != dirs {
+            continue;
+        }
+        found.push((
+            entry.file_name().to_string_lossy().into_owned(),
+            entry.path(),
+        ));
+    }
+    Ok(found)
+}
+
+/// Map every path the riotfile can build or read under `riot_root` to the venv hashes using it.
+#[must_use]
+pub fn references(
+    riot_root: &Path,
+    venvs: &IndexMap<String, RiotVenv>,
+) -> HashMap<PathBuf, Vec<String>> {
+    let mut refs: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    let mut add = |path: PathBuf, hash: &str| {
+        let owners = refs.entry(path).or_default();
+        if !owners.iter().any(|owner| owner == hash) {
+            owners.push(hash.to_string());
+        }
+    };
+
+    for venv in venvs.values() {
+        // `rt shell <venv hash>` builds a context named after the venv itself.
+        add(venv_path(riot_root, &venv.hash), &venv.hash);
+        for ctx in &venv.execution_contexts {
+            add(venv_path(riot_root, &ctx.hash), &venv.hash);
+        }
+        add(
+            get_deps_install_path(riot_root, &deps_key(venv)),
+            &venv.hash,
+        );
+        for no_editable in [false, true] {
+            add(
+                get_dev_install_path(riot_root, &venv.python, no_editable),
+                &venv.hash,
+            );
+        }
+        add(lockfile_path(riot_root, &venv.hash), &venv.hash);
+    }
+
+    refs
+}
+
+/// Total size in bytes of a file or directory tree, without following symlinks.
+#[must_use]
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| disk_usage(&entry.path()))
+            .sum()
+    })
+}
+
+/// Format a byte count with binary units (e.g. "1.5 GiB").
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Remove a file or directory tree.
+///
+/// # Errors
+///
+/// Returns an error if the removal fails.
+pub fn remove_entry(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Exclusive advisory lock on a build target, released when dropped.
+///
+/// Locks live in `<riot root>/.locks` rather than in the target itself so that the target can be
+/// removed and recreated while the lock is held.
+pub struct TargetLock {
+    _file: File,
+    waited: bool,
+}
+
+impl TargetLock {
+    /// Whether another process held the lock when it was requested.
+    #[must_use]
+    pub const fn waited(&self) -> bool {
+        self.waited
+    }
+}
+
+/// Path of the lock file guarding `target`.
+#[must_use]
+pub fn lock_path(riot_root: &Path, target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map_or_else(|| "root".into(), |name| name.to_string_lossy());
+    riot_root.join(LOCKS_DIR).join(format!("{name}.lock"))
+}
+
+/// Take the exclusive lock guarding `target`, calling `on_wait` before waiting if another
+/// process holds it. The wait is abandoned as soon as `cancelled` returns true.
+///
+/// # Errors
+///
+/// Returns an error if the lock file cannot be created or locked, and one of kind `Interrupted`
+/// if the wait was cancelled.
+pub fn lock_target(
+    riot_root: &Path,
+    target: &Path,
+    on_wait: impl FnOnce(),
+    cancelled: impl Fn() -> bool,
+) -> io::Result<TargetLock> {
+    let path = lock_path(riot_root, target);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+
+    let waited = match file.try_lock() {
+        Ok(()) => false,
+        Err(TryLockError::WouldBlock) => {
+            on_wait();
+            loop {
+                if cancelled() {
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+                }
+                match file.try_lock() {
+                    Ok(()) => break,
+                    Err(TryLockError::WouldBlock) => thread::sleep(LOCK_POLL_INTERVAL),
+                    Err(TryLockError::Error(err)) => return Err(err),
+                }
+            }
+            true
+        }
+        Err(TryLockError::Error(err)) => return Err(err),
+    };
+
+    Ok(TargetLock {
+        _file: file,
+        waited,
+    })
+}
+
+/// Whether another process currently holds the build lock of `target`.
+fn is_locked(riot_root: &Path, target: &Path) -> bool {
+    File::open(lock_path(riot_root, target))
+        .is_ok_and(|file| matches!(file.try_lock_shared(), Err(TryLockError::WouldBlock)))
+}
+
+/// Shared locks on the in-use markers of build trees, released when dropped.
+///
+/// The lock is taken on the marker inside the tree, so it follows the tree when a rebuild retires
+/// it: the retired tree is kept until every process running from it is done.
+pub struct InUse {
+    _files: Vec<File>,
+}
+
+/// Mark the build trees at `paths` as in use by this process. Trees that do not exist or
+/// predate in-use markers are skipped.
+#[must_use]
+pub fn mark_in_use(paths: &[PathBuf]) -> InUse {
+    InUse {
+        _files: paths
+            .iter()
+            .filter_map(|path| File::open(path.join(IN_USE_MARKER)).ok())
+            .filter(|file| file.try_lock_shared().is_ok())
+            .collect(),
+    }
+}
+
+/// Build trees an execution context of `venv` runs from: its own venv, the deps install and the
+/// dev install.
+#[must_use]
+pub fn context_builds(riot_root: &Path, venv: &RiotVenv, ctx: &ExecutionContext) -> Vec<PathBuf> {
+    let mut paths = vec![
+        venv_path(riot_root, &ctx.hash),
+        get_deps_install_path(riot_root, &deps_key(venv)),
+    ];
+    if !ctx.skip_dev_install {
+        for no_editable in [false, true] {
+            paths.push(get_dev_install_path(riot_root, &venv.python, no_editable));
+        }
+    }
+    paths
+}
+
+/// Whether a process holds the in-use marker of the build tree at `path`.
+fn in_use(path: &Path) -> bool {
+    File::open(path.join(IN_USE_MARKER))
+        .is_ok_and(|file| matches!(file.try_lock(), Err(TryLockError::WouldBlock)))
+}
+
+/// Suffixes of the sibling directories a build is staged in and the previous build is retired to.
+const STAGING_SUFFIXES: [&str; 2] = [".tmp-", ".old-"];
+
+/// Name of the build target a staging or retired directory belongs to.
+fn staged_target_name(name: &str) -> Option<&str> {
+    STAGING_SUFFIXES
+        .iter()
+        .find_map(|suffix| name.rsplit_once(suffix))
+        .map(|(target, _)| target)
+}
+
+fn sibling_path(target: &Path, suffix: &str) -> PathBuf {
+    let name = target
+        .file_name()
+        .map_or_else(|| "build".into(), |name| name.to_string_lossy());
+    target.with_file_name(format!("{name}{suffix}{}", std::process::id()))
+}
+
+/// Directory a build step installs into before it replaces its target in one rename.
+///
+/// Lifecycle of the sibling directories of a target, all created with its build lock held:
+///
+/// - `<target>.tmp-<pid>` is the staging directory. It is removed on drop unless it was promoted,
+///   so a failed build leaves the previous target untouched.
+/// - Promotion swaps it with the target, and the previous build is renamed to
+///   `<target>.old-<pid>`: processes may still be running from it (see [`mark_in_use`]).
+/// - The next build of the same target removes the staging and retired directories left by
+///   earlier builds, except those still in use. `rt gc` and `rt clean` remove them likewise.
+pub struct StagedDir {
+    path: PathBuf,
+    target: PathBuf,
+    promoted: bool,
+}
+
+impl StagedDir {
+    /// Create an empty staging directory next to `target`, removing those of earlier builds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn new(target: &Path) -> io::Result<Self> {
+        let path = sibling_path(target, STAGING_SUFFIXES[0]);
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        sweep_siblings(target);
+        fs::create_dir_all(&path)?;
+        Ok(Self {
+            path,
+            target: target.to_path_buf(),
+            promoted: false,
+        })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Move the staged build into place, replacing the previous target.
+    ///
+    /// The target is swapped with the staged directory in one atomic exchange, so it never
+    /// disappears for processes using it. The previous build is left behind in a retired sibling
+    /// directory, since they may still be running from it, until it is swept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the staged directory cannot be moved into place; the previous target
+    /// is then kept.
+    pub fn promote(mut self) -> io::Result<()> {
+        File::create(self.path.join(IN_USE_MARKER))?;
+        match exchange(&self.path, &self.target) {
+            Ok(()) => {
+                self.promoted = true;
+                // The staging path now holds the previous build; if it cannot be renamed, it is
+                // still cleaned up as a staging directory.
+                let _ = fs::rename(&self.path, retired_path(&self.target));
+                return Ok(());
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !self.target.exists() => {
+                fs::rename(&self.path, &self.target)?;
+                self.promoted = true;
+                return Ok(());
+            }
+            Err(err) if !exchange_unsupported(&err) => return Err(err),
+            Err(_) => {}
+        }
+
+        // Without an atomic exchange the target is briefly missing between the two renames.
+        let retired = retired_path(&self.target);
+        fs::rename(&self.target, &retired)?;
+        if let Err(err) = fs::rename(&self.path, &self.target) {
+            let _ = fs::rename(&retired, &self.target);
+            return Err(err);
+        }
+        self.promoted = true;
+        Ok(())
+    }
+}
+
+/// Remove the staging and retired directories of earlier builds of `target` that no process runs
+/// from.
+fn sweep_siblings(target: &Path) {
+    let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(parent) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if staged_target_name(&entry.file_name().to_string_lossy()) == name.to_str()
+            && !in_use(&path)
+        {
+            let _ = fs::remove_dir_all(&path);
+        }
+    }
+}
+
+/// Unused sibling directory to retire the current build of `target` to.
+fn retired_path(target: &Path) -> PathBuf {
+    let base = sibling_path(target, STAGING_SUFFIXES[1]);
+    let mut retired = base.clone();
+    let mut attempt = 1;
+    while retired.exists() {
+        retired = base.with_file_name(format!(
+            "{}-{attempt}",
+            base.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        attempt += 1;
+    }
+    retired
+}
+
+/// Atomically exchange the directories at `left` and `right`.
+#[cfg(target_os = "linux")]
+fn exchange(left: &Path, right: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let left = CString::new(left.as_os_str().as_bytes())?;
+    let right = CString::new(right.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid NUL-terminated strings for the duration of the call.
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            left.as_ptr(),
+            libc::AT_FDCWD,
+            right.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Atomically exchange the directories at `left` and `right`.
+#[cfg(target_os = "macos")]
+fn exchange(left: &Path, right: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let left = CString::new(left.as_os_str().as_bytes())?;
+    let right = CString::new(right.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid NUL-terminated strings for the duration of the call.
+    let result = unsafe { libc::renamex_np(left.as_ptr(), right.as_ptr(), libc::RENAME_SWAP) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn exchange(_left: &Path, _right: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Whether `err` means the platform or filesystem cannot exchange directories atomically.
+fn exchange_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported
+        || matches!(
+            err.raw_os_error(),
+            Some(libc::EINVAL | libc::ENOSYS | libc::ENOTSUP)
+        )
+}
+
+impl Drop for StagedDir {
+    fn drop(&mut self) {
+        if !self.promoted {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{EntryKind, StagedDir, mark_in_use, scan};
+
+    #[test]
+    fn staged_dir_replaces_target_and_cleans_up_failures() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("venv_deps/deps_0123");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("old"), "").unwrap();
+
+        let staged = StagedDir::new(&target).unwrap();
+        fs::write(staged.path().join("new"), "").unwrap();
+        staged.promote().unwrap();
+        assert!(target.join("new").is_file());
+        assert!(!target.join("old").exists());
+        let retired: Vec<_> = fs::read_dir(root.path().join("venv_deps"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path != &target)
+            .collect();
+        assert_eq!(retired.len(), 1);
+        assert!(retired[0].join("old").is_file());
+        fs::remove_dir_all(&retired[0]).unwrap();
+
+        let abandoned = StagedDir::new(&target).unwrap();
+        let abandoned_path = abandoned.path().to_path_buf();
+        drop(abandoned);
+        assert!(!abandoned_path.exists());
+        assert!(target.join("new").is_file());
+
+        fs::create_dir_all(root.path().join("venv_abc.tmp-1")).unwrap();
+        let kinds: Vec<EntryKind> = scan(root.path())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.kind)
+            .collect();
+        assert_eq!(kinds, [EntryKind::Deps, EntryKind::Staging]);
+    }
+
+    #[test]
+    fn retired_builds_are_swept_once_no_longer_in_use() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("venv_deps/deps_0123");
+        let siblings = || {
+            let mut names: Vec<_> = fs::read_dir(root.path().join("venv_deps"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name != "deps_0123")
+                .collect();
+            names.sort();
+            names
+        };
+        let rebuild = |content: &str| {
+            let staged = StagedDir::new(&target).unwrap();
+            fs::write(staged.path().join(content), "").unwrap();
+            staged.promote().unwrap();
+        };
+
+        rebuild("first");
+        let in_use = mark_in_use(std::slice::from_ref(&target));
+        rebuild("second");
+        let retired = siblings();
+        assert_eq!(retired.len(), 1);
+        assert!(
+            root.path()
+                .join("venv_deps")
+                .join(&retired[0])
+                .join("first")
+                .is_file()
+        );
+        assert!(
+            scan(root.path())
+                .unwrap()
+                .iter()
+                .all(|entry| entry.kind != EntryKind::Staging)
+        );
+
+        // Still in use: kept by the next build, which retires the second one.
+        rebuild("third");
+        assert_eq!(siblings().len(), 2);
+
+        drop(in_use);
+        rebuild("fourth");
+        let retired = siblings();
+        assert_eq!(retired.len(), 1);
+        assert!(
+            root.path()
+                .join("venv_deps")
+                .join(&retired[0])
+                .join("third")
+                .is_file()
+        );
+        assert!(target.join("fourth").is_file());
+    }
+}
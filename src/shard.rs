@@ -0,0 +1,200 @@
+//! Deterministic sharding of the selected execution contexts across CI jobs.
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    commands::build::collect_context_indices,
+    error::RtResult,
+    history::{self, ContextStatus},
+    ui,
+    venv::RiotVenv,
+};
+
+/// Group `index` (1-based) of the selected execution contexts split into `count` groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// Parse a shard given as `I/N`, e.g. `2/4`.
+///
+/// # Errors
+///
+/// Returns a description of the problem when the value is not a valid shard.
+pub fn parse_shard(value: &str) -> Result<Shard, String> {
+    let (index, count) = value
+        .split_once('/')
+        .ok_or_else(|| format!("expected I/N (e.g. 2/4), got \"{value}\""))?;
+    let parse = |part: &str| {
+        part.trim()
+            .parse::<usize>()
+            .map_err(|_| format!("expected I/N (e.g. 2/4), got \"{value}\""))
+    };
+    let shard = Shard {
+        index: parse(index)?,
+        count: parse(count)?,
+    };
+    if shard.count == 0 || shard.index == 0 || shard.index > shard.count {
+        return Err(format!(
+            "shard index must be between 1 and the shard count, got {shard}"
+        ));
+    }
+    Ok(shard)
+}
+
+/// How execution contexts are assigned to shards.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ShardBy {
+    /// By execution context hash; a context keeps its shard as others are added or removed.
+    #[default]
+    Hash,
+    /// Balance the durations recorded in the run history; contexts without one count as the
+    /// average. All jobs must share the same history to agree on the split.
+    Duration,
+}
+
+/// Narrow `selected` to the execution contexts of `shard`.
+///
+/// # Errors
+///
+/// Returns an error if the run history cannot be read when sharding by duration.
+pub fn select_shard(
+    mut selected: Vec<RiotVenv>,
+    riot_root: &Path,
+    shard: Shard,
+    by: ShardBy,
+) -> RtResult<Vec<RiotVenv>> {
+    let hashes: Vec<String> = collect_context_indices(&selected)
+        .into_iter()
+        .map(|(venv_i, exc_i)| selected[venv_i].execution_contexts[exc_i].hash.clone())
+        .collect();
+    let assignment = match by {
+        ShardBy::Hash => assign_by_hash(&hashes, shard.count),
+        ShardBy::Duration => {
+            assign_by_duration(&hashes, &recorded_durations(riot_root)?, shard.count)
+        }
+    };
+    let keep: Vec<&str> = hashes
+        .iter()
+        .zip(&assignment)
+        .filter(|(_, group)| **group == shard.index - 1)
+        .map(|(hash, _)| hash.as_str())
+        .collect();
+
+    for venv in &mut selected {
+        venv.execution_contexts
+            .retain(|ctx| keep.contains(&ctx.hash.as_str()));
+    }
+    selected.retain(|venv| !venv.execution_contexts.is_empty());
+    ui::step(format!(
+        "Shard {shard}: {} of {} execution context(s)",
+        keep.len(),
+        hashes.len()
+    ));
+    Ok(selected)
+}
+
+/// Group of each hash, from a digest of the hash alone.
+fn assign_by_hash(hashes: &[String], count: usize) -> Vec<usize> {
+    hashes
+        .iter()
+        .map(|hash| {
+            let digest = Sha256::digest(hash.as_bytes());
+            let mut prefix = [0u8; 8];
+            prefix.copy_from_slice(&digest[..8]);
+            let value = u64::from_be_bytes(prefix);
+            usize::try_from(value % count as u64).unwrap_or(0)
+        })
+        .collect()
+}
+
+/// Group of each hash, handing the longest contexts out first to the group with the least total
+/// duration so far.
+#[allow(clippy::cast_precision_loss)]
+fn assign_by_duration(
+    hashes: &[String],
+    durations: &HashMap<String, f64>,
+    count: usize,
+) -> Vec<usize> {
+    let known: Vec<f64> = hashes
+        .iter()
+        .filter_map(|hash| durations.get(hash).copied())
+        .collect();
+    let average = if known.is_empty() {
+        1.0
+    } else {
+        known.iter().sum::<f64>() / known.len() as f64
+    };
+
+    let mut order: Vec<usize> = (0..hashes.len()).collect();
+    let duration = |idx: usize| durations.get(&hashes[idx]).copied().unwrap_or(average);
+    order.sort_by(|&left, &right| {
+        duration(right)
+            .total_cmp(&duration(left))
+            .then_with(|| hashes[left].cmp(&hashes[right]))
+    });
+
+    let mut totals = vec![0.0_f64; count];
+    let mut assignment = vec![0; hashes.len()];
+    for idx in order {
+        let group = (0..count)
+            .min_by(|&left, &right| totals[left].total_cmp(&totals[right]))
+            .unwrap_or(0);
+        totals[group] += duration(idx);
+        assignment[idx] = group;
+    }
+    assignment
+}
+
+/// Duration of each execution context in the latest run that completed it.
+fn recorded_durations(riot_root: &Path) -> RtResult<HashMap<String, f64>> {
+    let mut durations = HashMap::new();
+    for record in history::load(riot_root)? {
+        for result in record.results {
+            if matches!(
+                result.status,
+                ContextStatus::Passed | ContextStatus::Failed | ContextStatus::Flaky
+            ) {
+                durations.insert(result.hash, result.duration_secs);
+            }
+        }
+    }
+    Ok(durations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{assign_by_duration, assign_by_hash, parse_shard};
+
+    #[test]
+    fn shards_partition_contexts_stably() {
+        assert_eq!(parse_shard("2/4").map(|shard| shard.index), Ok(2));
+        for invalid in ["0/4", "5/4", "1/0", "2", "a/b"] {
+            assert!(parse_shard(invalid).is_err(), "{invalid}");
+        }
+
+        let hashes: Vec<String> = (0..20).map(|i| format!("{i:07x}@13515d1")).collect();
+        let by_hash = assign_by_hash(&hashes, 3);
+        assert!(by_hash.iter().all(|group| *group < 3));
+        assert_eq!(assign_by_hash(&hashes[5..], 3), by_hash[5..]);
+
+        let durations: HashMap<String, f64> = [("a", 10.0), ("b", 6.0), ("c", 5.0), ("d", 4.0)]
+            .into_iter()
+            .map(|(hash, secs)| (hash.to_string(), secs))
+            .collect();
+        let hashes: Vec<String> = ["a", "b", "c", "d", "e"].map(str::to_string).to_vec();
+        // "e" has no recorded duration and counts as the average, 6.25s.
+        assert_eq!(assign_by_duration(&hashes, &durations, 2), [0, 1, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,350 @@
+//! Minimal PEP 440 version and specifier matching used to validate lockfiles.
+
+use std::cmp::Ordering;
+
+/// A parsed PEP 440 version (epoch and local labels are ignored).
+#[derive(Debug, Clone)]
+pub struct Version {
+    release: Vec<u64>,
+    pre: Option<(u8, u64)>,
+    post: Option<u64>,
+    dev: Option<u64>,
+}
+
+impl Version {
+    /// Parse a version string such as `1.2.3`, `2.0rc1`, `1.0.post2` or `3.0.dev0`.
+    #[must_use]
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        let value = value.strip_prefix('v').unwrap_or(&value);
+        let value = value.split_once('!').map_or(value, |(_, rest)| rest);
+        let value = value.split('+').next().unwrap_or(value);
+
+        let release_end = value
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(value.len());
+        let (release_part, mut rest) = value.split_at(release_end);
+        let release_part = release_part.trim_end_matches('.');
+        if release_part.is_empty() {
+            return None;
+        }
+        let release = release_part
+            .split('.')
+            .map(|part| part.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut version = Self {
+            release,
+            pre: None,
+            post: None,
+            dev: None,
+        };
+
+        while !rest.is_empty() {
+            rest = rest.trim_start_matches(['.', '-', '_']);
+            if let Some((kind, after)) = strip_pre_label(rest) {
+                let (number, after) = take_number(after);
+                version.pre = Some((kind, number));
+                rest = after;
+            } else if let Some(after) = rest
+                .strip_prefix("post")
+                .or_else(|| rest.strip_prefix("rev"))
+                .or_else(|| rest.strip_prefix('r'))
+            {
+                let (number, after) = take_number(after.trim_start_matches(['.', '-', '_']));
+                version.post = Some(number);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("dev") {
+                let (number, after) = take_number(after.trim_start_matches(['.', '-', '_']));
+                version.dev = Some(number);
+                rest = after;
+            } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                // Implicit post release (`1.0-1`).
+                let (number, after) = take_number(rest);
+                version.post = Some(number);
+                rest = after;
+            } else if rest.is_empty() {
+                break;
+            } else {
+                return None;
+            }
+        }
+
+        Some(version)
+    }
+
+    fn compare_release(&self, other: &Self) -> Ordering {
+        let len = self.release.len().max(other.release.len());
+        (0..len)
+            .map(|idx| {
+                let left = self.release.get(idx).copied().unwrap_or(0);
+                let right = other.release.get(idx).copied().unwrap_or(0);
+                left.cmp(&right)
+            })
+            .find(|ord| *ord != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+
+    const fn phase_key(&self) -> (u8, u8, u64) {
+        match (self.pre, self.post, self.dev) {
+            // A bare dev release sorts before every pre-release of the same version.
+            (None, None, Some(_)) => (0, 0, 0),
+            (Some((kind, number)), _, _) => (1, kind, number),
+            _ => (2, 0, 0),
+        }
+    }
+
+    const fn is_prerelease(&self) -> bool {
+        self.pre.is_some() || self.dev.is_some()
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.compare_release(other)
+            .then_with(|| self.phase_key().cmp(&other.phase_key()))
+            .then_with(|| {
+                let left = self.post.map_or(-1, i128::from);
+                let right = other.post.map_or(-1, i128::from);
+                left.cmp(&right)
+            })
+            .then_with(|| {
+                let left = self.dev.map_or(i128::MAX, i128::from);
+                let right = other.dev.map_or(i128::MAX, i128::from);
+                left.cmp(&right)
+            })
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn strip_pre_label(value: &str) -> Option<(u8, &str)> {
+    const LABELS: &[(&str, u8)] = &[
+        ("alpha", 0),
+        ("a", 0),
+        ("beta", 1),
+        ("b", 1),
+        ("preview", 2),
+        ("pre", 2),
+        ("rc", 2),
+        ("c", 2),
+    ];
+    LABELS.iter().find_map(|(label, kind)| {
+        value
+            .strip_prefix(label)
+            .map(|rest| (*kind, rest.trim_start_matches(['.', '-', '_'])))
+    })
+}
+
+fn take_number(value: &str) -> (u64, &str) {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, rest) = value.split_at(end);
+    (digits.parse().unwrap_or(0), rest)
+}
+
+/// Normalize a distribution name following PEP 503 (`Zope.Interface` -> `zope-interface`).
+#[must_use]
+pub fn normalize_name(name: &str) -> String {
+    let name = name.split('[').next().unwrap_or(name).trim();
+    let mut normalized = String::with_capacity(name.len());
+    for ch in name.chars() {
+        if matches!(ch, '-' | '_' | '.') {
+            if !normalized.ends_with('-') {
+                normalized.push('-');
+            }
+        } else {
+            normalized.push(ch.to_ascii_lowercase());
+        }
+    }
+    normalized
+}
+
+/// Result of checking a version against a riotfile constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    Satisfied,
+    Violated,
+    /// The constraint is a direct reference or otherwise not a version specifier.
+    Unchecked,
+}
+
+/// Check whether `version` satisfies a riotfile constraint such as `>=1.0,<2` or `~=3.0`.
+///
+/// Leading extras (`[toml]>=7`) and trailing environment markers are ignored. An empty
+/// constraint accepts every version.
+#[must_use]
+pub fn matches_constraint(constraint: &str, version: &str) -> Match {
+    let mut spec = constraint.trim();
+    if spec.starts_with('[')
+        && let Some(end) = spec.find(']')
+    {
+        spec = spec[end + 1..].trim();
+    }
+    let spec = spec.split(';').next().unwrap_or(spec).trim();
+    if spec.is_empty() {
+        return Match::Satisfied;
+    }
+    if spec.starts_with('@') {
+        return Match::Unchecked;
+    }
+
+    let Some(version) = Version::parse(version) else {
+        return Match::Unchecked;
+    };
+
+    for clause in spec.split(',') {
+        match clause_matches(clause.trim(), &version) {
+            Some(true) => {}
+            Some(false) => return Match::Violated,
+            None => return Match::Unchecked,
+        }
+    }
+    Match::Satisfied
+}
+
+fn clause_matches(clause: &str, version: &Version) -> Option<bool> {
+    const OPERATORS: &[&str] = &["===", "~=", "==", "!=", "<=", ">=", "<", ">"];
+    let op = OPERATORS.iter().find(|op| clause.starts_with(**op))?;
+    let target = clause[op.len()..].trim();
+
+    if let Some(prefix) = target.strip_suffix(".*") {
+        let prefix = Version::parse(prefix)?;
+        let matched = version.release.len() >= prefix.release.len()
+            && version.release[..prefix.release.len()] == prefix.release[..];
+        return match *op {
+            "==" => Some(matched),
+            "!=" => Some(!matched),
+            _ => None,
+        };
+    }
+
+    if *op == "===" {
+        return Some(version_string_eq(version, target));
+    }
+
+    let target = Version::parse(target)?;
+    let ord = version.cmp(&target);
+    Some(match *op {
+        "==" => ord == Ordering::Equal,
+        "!=" => ord != Ordering::Equal,
+        "<=" => ord != Ordering::Greater,
+        ">=" => ord != Ordering::Less,
+        // Exclusive bounds do not admit pre-releases of the bound itself, unless the bound is a
+        // pre-release too.
+        "<" => {
+            ord == Ordering::Less
+                && !(version.is_prerelease()
+                    && !target.is_prerelease()
+                    && version.compare_release(&target).is_eq())
+        }
+        // Nor post-releases of it, unless the bound is a post-release itself.
+        ">" => {
+            ord == Ordering::Greater
+                && !(target.post.is_none()
+                    && version.post.is_some()
+                    && version.compare_release(&target).is_eq()
+                    && version.pre == target.pre)
+        }
+        "~=" => {
+            if target.release.len() < 2 {
+                return None;
+            }
+            let prefix = &target.release[..target.release.len() - 1];
+            ord != Ordering::Less
+                && version.release.len() >= prefix.len()
+                && version.release[..prefix.len()] == prefix[..]
+        }
+        _ => return None,
+    })
+}
+
+fn version_string_eq(version: &Version, target: &str) -> bool {
+    Version::parse(target).is_some_and(|parsed| parsed == *version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Match, Version, matches_constraint, normalize_name};
+
+    #[test]
+    fn version_ordering_follows_pep440_phases() {
+        let ordered = [
+            "1.0.dev1",
+            "1.0a1",
+            "1.0b2",
+            "1.0rc1",
+            "1.0",
+            "1.0.post1",
+            "1.1",
+        ];
+        let parsed: Vec<Version> = ordered
+            .iter()
+            .map(|value| Version::parse(value).unwrap())
+            .collect();
+        for pair in parsed.windows(2) {
+            assert!(pair[0] < pair[1], "{pair:?}");
+        }
+        assert_eq!(
+            Version::parse("1.0")
+                .unwrap()
+                .cmp(&Version::parse("1.0.0").unwrap()),
+            std::cmp::Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn constraint_ranges() {
+        assert_eq!(matches_constraint(">=5.0", "5.4.3"), Match::Satisfied);
+        assert_eq!(matches_constraint(">=5.0", "4.9"), Match::Violated);
+        assert_eq!(matches_constraint(">=1,<2", "2.0"), Match::Violated);
+        assert_eq!(matches_constraint("<2", "2.0rc1"), Match::Violated);
+        assert_eq!(matches_constraint("<2.0rc2", "2.0rc1"), Match::Satisfied);
+        assert_eq!(matches_constraint("", "0.1"), Match::Satisfied);
+        assert_eq!(matches_constraint(">1.0rc1", "1.0"), Match::Satisfied);
+        assert_eq!(matches_constraint(">1.0", "1.0.post1"), Match::Violated);
+        assert_eq!(
+            matches_constraint(">1.0.post1", "1.0.post2"),
+            Match::Satisfied
+        );
+        assert_eq!(matches_constraint(">1.0", "1.0.1"), Match::Satisfied);
+    }
+
+    #[test]
+    fn constraint_compatible_and_wildcards() {
+        assert_eq!(matches_constraint("~=3.0", "3.9.1"), Match::Satisfied);
+        assert_eq!(matches_constraint("~=3.0", "4.0"), Match::Violated);
+        assert_eq!(matches_constraint("==2.*", "2.31.0"), Match::Satisfied);
+        assert_eq!(matches_constraint("!=2.*", "2.31.0"), Match::Violated);
+        assert_eq!(matches_constraint("==5.4.3", "5.4.3"), Match::Satisfied);
+    }
+
+    #[test]
+    fn constraint_extras_and_direct_references() {
+        assert_eq!(matches_constraint("[toml]>=7", "7.6.8"), Match::Satisfied);
+        assert_eq!(
+            matches_constraint(" @ git+https://github.com/x/y", "1.0"),
+            Match::Unchecked
+        );
+    }
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(normalize_name("Zope.Interface"), "zope-interface");
+        assert_eq!(normalize_name("pytest_asyncio"), "pytest-asyncio");
+        assert_eq!(normalize_name("coverage[toml]"), "coverage");
+    }
+}
@@ -0,0 +1,394 @@
+//! Step logs: the captured output of every build and run step, written to a file under the riot
+//! root so it outlives the in-memory display. Each step keeps its most recent logs, named after
+//! the time the step started, with one log per attempt of a retried step.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    constants::LOGS_DIR,
+    display::{StepStatus, strip_ansi},
+    progress::{OutputPolicy, OutputStream, ProgressLogger, StepId},
+};
+
+/// Runs of a step whose logs are kept; older ones are removed when a step finishes.
+const LOGS_PER_STEP: usize = 10;
+
+/// Prefix of the last line of a log, written once its step finished.
+pub const FINISHED_MARKER: &str = "==> finished: ";
+
+/// Command whose steps are logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogPhase {
+    Build,
+    Run,
+}
+
+impl LogPhase {
+    const fn dir_name(self) -> &'static str {
+        match self {
+            Self::Build => "build",
+            Self::Run => "run",
+        }
+    }
+}
+
+/// Directory holding the logs of step `step_id` in `phase`.
+#[must_use]
+pub fn step_log_dir(riot_root: &Path, phase: LogPhase, step_id: &str) -> PathBuf {
+    let name: String = step_id
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || matches!(ch, '@' | '.' | '-') {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    riot_root.join(LOGS_DIR).join(phase.dir_name()).join(name)
+}
+
+/// The most recent log among the steps of `steps`, if any was recorded: the last attempt of the
+/// latest run of these steps.
+#[must_use]
+pub fn latest(riot_root: &Path, steps: &[(LogPhase, String)]) -> Option<PathBuf> {
+    steps
+        .iter()
+        .flat_map(|(phase, step_id)| step_logs(&step_log_dir(riot_root, *phase, step_id)))
+        .max_by_key(|(key, _)| *key)
+        .map(|(_, path)| path)
+}
+
+/// The log of attempt `attempt` of the run that wrote `log`, if it was recorded.
+#[must_use]
+pub fn attempt_log(log: &Path, attempt: u32) -> Option<PathBuf> {
+    let (started, _) = log_key(log)?;
+    let path = log.with_file_name(log_file_name(started, attempt));
+    path.is_file().then_some(path)
+}
+
+/// The log of the attempt following the one that wrote `log`, if it was started.
+#[must_use]
+pub fn next_attempt_log(log: &Path) -> Option<PathBuf> {
+    let (_, attempt) = log_key(log)?;
+    attempt_log(log, attempt + 1)
+}
+
+/// Name of the log of attempt `attempt` of a run started at `started` milliseconds since the
+/// Unix epoch.
+fn log_file_name(started: u128, attempt: u32) -> String {
+    if attempt > 1 {
+        format!("{started:013}.attempt-{attempt}.log")
+    } else {
+        format!("{started:013}.log")
+    }
+}
+
+/// Start time and attempt of a log named by `log_file_name`.
+fn log_key(path: &Path) -> Option<(u128, u32)> {
+    let stem = path.file_name()?.to_str()?.strip_suffix(".log")?;
+    match stem.split_once(".attempt-") {
+        Some((started, attempt)) => Some((started.parse().ok()?, attempt.parse().ok()?)),
+        None => Some((stem.parse().ok()?, 1)),
+    }
+}
+
+/// Log files of a step directory with their start time and attempt, oldest first.
+fn step_logs(dir: &Path) -> Vec<((u128, u32), PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut logs: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            Some((log_key(&path)?, path))
+        })
+        .collect();
+    logs.sort();
+    logs
+}
+
+/// Remove the logs of all but the `LOGS_PER_STEP` latest runs in the step directory `dir`.
+fn prune(dir: &Path) {
+    let logs = step_logs(dir);
+    let mut runs: Vec<u128> = logs.iter().map(|((started, _), _)| *started).collect();
+    runs.dedup();
+    let Some(&oldest_kept) = runs.get(runs.len().saturating_sub(LOGS_PER_STEP)) else {
+        return;
+    };
+    for (_, old) in logs
+        .iter()
+        .filter(|((started, _), _)| *started < oldest_kept)
+    {
+        let _ = fs::remove_file(old);
+    }
+}
+
+/// Wrap `inner` so that the output of every step it reports is also written to a log file.
+#[must_use]
+pub fn record(
+    inner: Arc<dyn ProgressLogger>,
+    riot_root: &Path,
+    phase: LogPhase,
+) -> Arc<dyn ProgressLogger> {
+    Arc::new(StepLogger {
+        inner,
+        riot_root: riot_root.to_path_buf(),
+        phase,
+        labels: Mutex::new(HashMap::new()),
+        open: Mutex::new(HashMap::new()),
+    })
+}
+
+/// Progress sink forwarding everything to `inner` while logging each step to its own file.
+struct StepLogger {
+    inner: Arc<dyn ProgressLogger>,
+    riot_root: PathBuf,
+    phase: LogPhase,
+    labels: Mutex<HashMap<StepId, String>>,
+    open: Mutex<HashMap<StepId, OpenLog>>,
+}
+
+struct OpenLog {
+    path: PathBuf,
+    file: File,
+    /// Milliseconds since the Unix epoch at which the first attempt started.
+    run_started: u128,
+    started_at: Instant,
+    /// Whether anything besides the header was written.
+    written: bool,
+}
+
+impl StepLogger {
+    /// Create the log of step `id`, named after the current time.
+    fn open(&self, id: &StepId) -> io::Result<OpenLog> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis());
+        self.open_attempt(id, millis, None)
+    }
+
+    /// Create the log of step `id` for a run started at `run_started`, or for one of its retries
+    /// when `attempt` is given along with the number of attempts.
+    fn open_attempt(
+        &self,
+        id: &StepId,
+        run_started: u128,
+        attempt: Option<(u32, u32)>,
+    ) -> io::Result<OpenLog> {
+        let dir = step_log_dir(&self.riot_root, self.phase, id.as_str());
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(log_file_name(
+            run_started,
+            attempt.map_or(1, |(attempt, _)| attempt),
+        ));
+        let mut file = File::create(&path)?;
+        let label = self.labels.lock().unwrap().get(id).cloned();
+        let label = label.as_deref().unwrap_or(id.as_str());
+        match attempt {
+            Some((attempt, attempts)) => {
+                writeln!(file, "==> {label} (attempt {attempt}/{attempts})")?;
+            }
+            None => writeln!(file, "==> {label}")?,
+        }
+        if self.output_policy() == OutputPolicy::Inherit {
+            writeln!(
+                file,
+                "(output went straight to the terminal and was not captured)"
+            )?;
+        }
+        Ok(OpenLog {
+            path,
+            file,
+            run_started,
+            started_at: Instant::now(),
+            written: false,
+        })
+    }
+
+    /// Append `text` to the log of step `id`, if it has one.
+    fn write(&self, id: &StepId, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(log) = self.open.lock().unwrap().get_mut(id) {
+            let _ = log.file.write_all(strip_ansi(text).as_bytes());
+            log.written = true;
+        }
+    }
+}
+
+impl ProgressLogger for StepLogger {
+    fn register_step(&self, id: &StepId, label: &str) {
+        self.labels
+            .lock()
+            .unwrap()
+            .insert(id.clone(), label.to_string());
+        self.inner.register_step(id, label);
+    }
+
+    fn start(&self, id: &StepId) {
+        let mut open = self.open.lock().unwrap();
+        if !open.contains_key(id)
+            && let Ok(log) = self.open(id)
+        {
+            open.insert(id.clone(), log);
+        }
+        drop(open);
+        self.inner.start(id);
+    }
+
+    fn wait(&self, id: &StepId, reason: &str) {
+        self.write(id, &format!("[waiting] {reason}\n"));
+        self.inner.wait(id, reason);
+    }
+
+    fn retry(&self, id: &StepId, attempt: u32, attempts: u32) {
+        let mut open = self.open.lock().unwrap();
+        if let Some(mut log) = open.remove(id) {
+            // The next log exists before this one is finished, so that a reader following this
+            // attempt can move on to it.
+            if let Ok(next) = self.open_attempt(id, log.run_started, Some((attempt, attempts))) {
+                open.insert(id.clone(), next);
+            }
+            let _ = writeln!(
+                log.file,
+                "{FINISHED_MARKER}attempt failed after {:.1}s, retrying",
+                log.started_at.elapsed().as_secs_f64()
+            );
+        }
+        drop(open);
+        self.inner.retry(id, attempt, attempts);
+    }
+
+    fn finish(&self, id: &StepId, status: StepStatus) {
+        self.inner.finish(id, status);
+        let Some(mut log) = self.open.lock().unwrap().remove(id) else {
+            return;
+        };
+        // A cached step did nothing worth keeping.
+        if status == StepStatus::Cached && !log.written {
+            drop(log.file);
+            let _ = fs::remove_file(&log.path);
+            if let Some(dir) = log.path.parent() {
+                // Only succeeds when no earlier log is left.
+                let _ = fs::remove_dir(dir);
+            }
+            return;
+        }
+        let _ = writeln!(
+            log.file,
+            "{FINISHED_MARKER}{} after {:.1}s",
+            status.label(),
+            log.started_at.elapsed().as_secs_f64()
+        );
+        drop(log.file);
+        if let Some(dir) = log.path.parent() {
+            prune(dir);
+        }
+    }
+
+    fn append_output(&self, id: &StepId, line: String) {
+        self.write(id, &format!("{line}\n"));
+        self.inner.append_output(id, line);
+    }
+
+    fn append_output_chunk(&self, id: &StepId, chunk: &[u8]) {
+        self.write(id, &String::from_utf8_lossy(chunk));
+        self.inner.append_output_chunk(id, chunk);
+    }
+
+    fn append_stream_chunk(&self, id: &StepId, stream: OutputStream, chunk: &[u8]) {
+        self.write(id, &String::from_utf8_lossy(chunk));
+        self.inner.append_stream_chunk(id, stream, chunk);
+    }
+
+    fn flush_output(&self, id: &StepId) {
+        self.inner.flush_output(id);
+    }
+
+    fn output_policy(&self) -> OutputPolicy {
+        match self.inner.output_policy() {
+            // Build output meant for the terminal is still read through here, so it also reaches
+            // the log. Run steps keep the terminal: their commands may be interactive.
+            OutputPolicy::Inherit if self.phase == LogPhase::Build => OutputPolicy::Tee,
+            policy => policy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{FINISHED_MARKER, LogPhase, attempt_log, latest, record};
+    use crate::{
+        display::StepStatus,
+        progress::{OutputStream, PlainProgressLogger, StepId},
+    };
+
+    #[test]
+    fn logs_step_output_and_drops_cached_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = record(
+            Arc::new(PlainProgressLogger::default()),
+            dir.path(),
+            LogPhase::Build,
+        );
+        let failed = StepId::new("create execution context 2b340ba@13515d1");
+        let cached = StepId::new("dev install 3.12");
+        for id in [&failed, &cached] {
+            sink.register_step(id, id.as_str());
+            sink.start(id);
+        }
+        sink.append_stream_chunk(
+            &failed,
+            OutputStream::Stderr,
+            b"\x1b[31merror\x1b[0m: boom\n",
+        );
+        sink.finish(&failed, StepStatus::Failed);
+        sink.finish(&cached, StepStatus::Cached);
+
+        let steps = |id: &StepId| [(LogPhase::Build, id.as_str().to_string())];
+        assert!(latest(dir.path(), &steps(&cached)).is_none());
+        let log = std::fs::read_to_string(latest(dir.path(), &steps(&failed)).unwrap()).unwrap();
+        assert!(log.starts_with("==> create execution context 2b340ba@13515d1\n"));
+        assert!(log.contains("\nerror: boom\n"));
+        let last = log.lines().last().unwrap();
+        assert!(last.starts_with(&format!("{FINISHED_MARKER}failed after")));
+    }
+
+    #[test]
+    fn keeps_one_log_per_attempt() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = record(
+            Arc::new(PlainProgressLogger::default()),
+            dir.path(),
+            LogPhase::Run,
+        );
+        let id = StepId::new("2b340ba@13515d1");
+        sink.register_step(&id, "Execute 2b340ba@13515d1");
+        sink.start(&id);
+        sink.append_output(&id, "first".to_string());
+        sink.retry(&id, 2, 2);
+        sink.append_output(&id, "second".to_string());
+        sink.finish(&id, StepStatus::Flaky);
+
+        let last = latest(dir.path(), &[(LogPhase::Run, id.as_str().to_string())]).unwrap();
+        let log = std::fs::read_to_string(&last).unwrap();
+        assert!(log.starts_with("==> Execute 2b340ba@13515d1 (attempt 2/2)\nsecond\n"));
+        assert!(!log.contains("first"));
+
+        let log = std::fs::read_to_string(attempt_log(&last, 1).unwrap()).unwrap();
+        assert!(log.starts_with("==> Execute 2b340ba@13515d1\nfirst\n"));
+        assert!(log.lines().last().unwrap().starts_with(FINISHED_MARKER));
+        assert!(attempt_log(&last, 3).is_none());
+    }
+}
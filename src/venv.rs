@@ -2,6 +2,7 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use fancy_regex::Regex;
 use indexmap::{IndexMap, IndexSet};
@@ -10,10 +11,12 @@ use sha2::{Digest, Sha256};
 use shell_words::split;
 
 use crate::{
-    config::Selector,
+    config::{Selector, VenvScope},
     config_provider::{ConfigProvider, ProviderServices, ProviderVenvNode},
     constants::{REQUIREMENTS_DIR, VENV_PREFIX},
     error::{RtError, RtResult},
+    query::{Query, is_query},
+    specifier::{Match, matches_constraint, normalize_name},
 };
 
 #[derive(Clone)]
@@ -27,13 +30,36 @@ pub struct RiotVenv {
     pub display_pkgs: IndexMap<String, String>,
     pub hash: String,
     pub services: Vec<String>,
+    /// Path patterns of the suitespec suite named like this venv.
+    pub suite_paths: Vec<String>,
     pub execution_contexts: Vec<ExecutionContext>,
     pub shared_pkgs: IndexMap<String, String>,
     pub shared_env: IndexMap<String, String>,
+    /// `pkgs` of the rt.toml `[[venv]]` sections matching this venv. They are installed on top of
+    /// the riotfile packages or lockfile pins but never locked themselves.
+    pub scoped_pkgs: IndexMap<String, String>,
+    /// `env.build` of the rt.toml `[[venv]]` sections matching this venv.
+    pub build_env: IndexMap<String, String>,
+    /// `env.run` of the rt.toml `[[venv]]` sections matching this venv.
+    pub run_env: IndexMap<String, String>,
+    /// `timeout` of the last rt.toml `[[venv]]` section matching this venv that sets one.
+    pub timeout: Option<Duration>,
+    /// `retries` of the last rt.toml `[[venv]]` section matching this venv that sets them.
+    pub retries: Option<u32>,
 }
 
 impl RiotVenv {
-    fn new(
+    /// Packages requested for this venv: the riotfile packages, added to or replaced by those of
+    /// its `[[venv]]` sections.
+    #[must_use]
+    pub fn installed_pkgs(&self) -> IndexMap<String, String> {
+        let mut pkgs = self.pkgs.clone();
+        pkgs.extend(self.scoped_pkgs.clone());
+        pkgs
+    }
+
+    #[must_use]
+    pub fn new(
         name: String,
         python: String,
         pkgs: IndexMap<String, String>,
@@ -48,9 +74,15 @@ impl RiotVenv {
             display_pkgs: IndexMap::new(),
             hash,
             services,
+            suite_paths: Vec::new(),
             execution_contexts: Vec::new(),
             shared_pkgs: IndexMap::new(),
             shared_env: IndexMap::new(),
+            scoped_pkgs: IndexMap::new(),
+            build_env: IndexMap::new(),
+            run_env: IndexMap::new(),
+            timeout: None,
+            retries: None,
         }
     }
 }
@@ -94,6 +126,14 @@ pub fn venv_path(riot_root: &Path, short_hash: &str) -> PathBuf {
     riot_root.join(format!("{}{}", VENV_PREFIX, short_hash.replace('@', "_")))
 }
 
+/// Path of the compiled requirements lockfile for a venv hash.
+#[must_use]
+pub fn lockfile_path(riot_root: &Path, venv_hash: &str) -> PathBuf {
+    riot_root
+        .join(REQUIREMENTS_DIR)
+        .join(format!("{venv_hash}.txt"))
+}
+
 #[must_use]
 pub fn venv_python_path(riot_root: &Path, short_hash: &str) -> String {
     venv_path(riot_root, short_hash)
@@ -178,7 +218,13 @@ pub fn load_context<P: ConfigProvider>(
     riot_root: Option<&Path>,
 ) -> RtResult<IndexMap<String, RiotVenv>> {
     let loaded = P::load(riotfile_path)?;
-    Ok(normalize_venvs(&loaded.root, loaded.services.as_ref(), riot_root))
+    let mut venvs = normalize_venvs(&loaded.root, loaded.services.as_ref(), riot_root);
+    if let Some(suite_paths) = &loaded.suite_paths {
+        for venv in venvs.values_mut() {
+            venv.suite_paths = suite_paths.get(&venv.name).cloned().unwrap_or_default();
+        }
+    }
+    Ok(venvs)
 }
 
 fn normalize_venvs(
@@ -198,6 +244,29 @@ fn normalize_venvs(
     venvs
 }
 
+/// Apply the rt.toml `[[venv]]` sections matching each venv: extra packages and env tables are
+/// kept on the venv for build and run steps.
+pub fn apply_venv_scopes(venvs: &mut IndexMap<String, RiotVenv>, scopes: &[VenvScope]) {
+    if scopes.is_empty() {
+        return;
+    }
+    for venv in venvs.values_mut() {
+        for scope in scopes {
+            if !scope.matches(venv) {
+                continue;
+            }
+            venv.scoped_pkgs.extend(scope.pkgs.clone());
+            venv.build_env.extend(scope.build_env.clone());
+            venv.run_env.extend(scope.run_env.clone());
+            venv.timeout = scope.timeout.or(venv.timeout);
+            venv.retries = scope.retries.or(venv.retries);
+        }
+        venv.build_env.sort_keys();
+        venv.run_env.sort_keys();
+        venv.display_pkgs = build_display_pkgs(&venv.installed_pkgs(), &venv.resolved_pkgs);
+    }
+}
+
 fn collect_riot_venvs(
     venv: &ProviderVenvNode,
     state: &ResolvedSpec,
@@ -309,7 +378,8 @@ where
 ///
 /// Lines are expected to follow the `package==version` format produced by
 /// `pip-compile`. Comment lines, blank lines, and extras markers (e.g.
-/// `package[extra]==version`) are handled gracefully.
+/// `package[extra]==version`) are handled gracefully. Direct references
+/// (`package @ url`) map to `@ url`.
 fn parse_lockfile(content: &str) -> IndexMap<String, String> {
     let mut resolved = IndexMap::new();
     for line in content.lines() {
@@ -317,11 +387,24 @@ fn parse_lockfile(content: &str) -> IndexMap<String, String> {
         if line.is_empty() || line.starts_with('#') || line.starts_with('-') {
             continue;
         }
-        // Strip trailing inline comments (e.g. "pkg==1.0  # via ...")
-        let line = line.split('#').next().unwrap_or(line).trim();
+        // Strip trailing inline comments (e.g. "pkg==1.0  # via ..."); a URL fragment has no
+        // whitespace before its '#'.
+        let line = line.split(" #").next().unwrap_or(line).trim();
+
+        if let Some((name_part, url)) = line.split_once('@')
+            && !name_part.contains("==")
+        {
+            // Environment markers of a direct reference follow whitespace and ';'.
+            let url = url.split(" ;").next().unwrap_or(url).trim();
+            let name = name_part.split('[').next().unwrap_or(name_part).trim();
+            if !name.is_empty() && !url.is_empty() {
+                resolved.insert(name.to_lowercase(), format!("@ {url}"));
+            }
+            continue;
+        }
+
         // Strip environment markers after ';'
         let line = line.split(';').next().unwrap_or(line).trim();
-
         if let Some((name_part, version)) = line.split_once("==") {
             // Strip extras: "package[toml]" -> "package"
             let name = name_part.split('[').next().unwrap_or(name_part).trim();
@@ -336,11 +419,7 @@ fn parse_lockfile(content: &str) -> IndexMap<String, String> {
 /// Read and parse the lockfile for a given venv hash from the riot requirements directory.
 /// Returns an empty map if the lockfile does not exist.
 fn load_resolved_pkgs(riot_root: &Path, venv_hash: &str) -> IndexMap<String, String> {
-    let lockfile_path = riot_root
-        .join(REQUIREMENTS_DIR)
-        .join(format!("{venv_hash}.txt"));
-
-    fs::read_to_string(&lockfile_path)
+    fs::read_to_string(lockfile_path(riot_root, venv_hash))
         .map_or_else(|_| IndexMap::new(), |content| parse_lockfile(&content))
 }
 
@@ -386,6 +465,48 @@ fn build_display_pkgs(
     display
 }
 
+/// A constraint from the riotfile that the compiled lockfile no longer honours.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockfileProblem {
+    pub package: String,
+    pub detail: String,
+}
+
+/// Compare the riotfile constraints of a venv against its resolved lockfile pins.
+///
+/// Reports constrained packages that are missing from the lockfile and pins that fall outside
+/// their constraint. Direct references (`pkg @ url`), in the riotfile or the lockfile, are only
+/// checked for presence.
+#[must_use]
+pub fn lockfile_problems(
+    pkgs: &IndexMap<String, String>,
+    resolved_pkgs: &IndexMap<String, String>,
+) -> Vec<LockfileProblem> {
+    let resolved: HashMap<String, &String> = resolved_pkgs
+        .iter()
+        .map(|(name, version)| (normalize_name(name), version))
+        .collect();
+
+    let mut problems = Vec::new();
+    for (name, constraint) in pkgs {
+        let Some(version) = resolved.get(&normalize_name(name)) else {
+            problems.push(LockfileProblem {
+                package: name.clone(),
+                detail: format!("{name}{constraint} is not pinned in the lockfile"),
+            });
+            continue;
+        };
+
+        if !version.starts_with('@') && matches_constraint(constraint, version) == Match::Violated {
+            problems.push(LockfileProblem {
+                package: name.clone(),
+                detail: format!("{name}=={version} does not satisfy {constraint}"),
+            });
+        }
+    }
+    problems
+}
+
 fn pip_deps(pkgs: &IndexMap<String, String>) -> String {
     let mut parts = Vec::with_capacity(pkgs.len());
     for (lib, version) in pkgs {
@@ -553,6 +674,12 @@ impl RiotHasher {
     }
 }
 
+/// Whether `command` invokes pytest.
+#[must_use]
+pub fn is_pytest_command(command: &str) -> bool {
+    split(command).is_ok_and(|tokens| tokens.iter().any(|token| token == "pytest"))
+}
+
 fn parse_pytest_targets(command: &str) -> Vec<String> {
     let Some(tokens) = split(command).ok() else {
         return Vec::new();
@@ -595,6 +722,12 @@ fn filter_by_test_target(venv: &mut RiotVenv, test_target: &str) {
     });
 }
 
+/// Whether `ident` is a venv or execution context hash rather than a name or expression.
+#[must_use]
+pub fn is_hash(ident: &str) -> bool {
+    is_short_hash(ident) || parse_ctx_hash(ident).is_some()
+}
+
 fn is_short_hash(ident: &str) -> bool {
     ident.len() == 7 && ident.chars().all(|c| char::is_ascii_hexdigit(&c))
 }
@@ -642,6 +775,16 @@ pub fn select_execution_contexts(
 
     if let Some(python_selector) = python_selector {
         let selected: IndexSet<_> = python_selector.into_iter().collect();
+        let venv_hash = parse_ctx_hash(&pattern_selector).unwrap_or(&pattern_selector);
+        if let Some(venv) = venvs.get(venv_hash)
+            && !selected.contains(&venv.python)
+        {
+            return Err(RtError::message(format!(
+                "error: {pattern_selector} uses Python {}, which is not among the selected versions ({})",
+                venv.python,
+                selected.iter().join(", ")
+            )));
+        }
         venvs.retain(|_, venv| selected.contains(&venv.python));
     }
     let shared_pkgs_map = shared_pkgs_by_name(venvs.values());
@@ -673,6 +816,21 @@ pub fn select_execution_contexts(
         return Ok(vec![venv]);
     }
 
+    if is_query(&pattern_selector) {
+        let query = Query::parse(&pattern_selector)?;
+        let mut selected_envs = Vec::new();
+        for (_, mut venv) in venvs {
+            if query.select(&mut venv) {
+                venv.shared_pkgs = shared_pkgs_map.get(&venv.name).cloned().unwrap_or_default();
+                if let Some(ref test_target) = test_selector {
+                    filter_by_test_target(&mut venv, test_target);
+                }
+                selected_envs.push(venv);
+            }
+        }
+        return Ok(selected_envs);
+    }
+
     let name_regex = Regex::new(&pattern_selector)
         .map_err(|err| RtError::message(format!("error: invalid name pattern: {err}")))?;
 
@@ -695,7 +853,9 @@ pub fn select_execution_contexts(
 
 #[cfg(test)]
 mod tests {
-    use super::{format_display_version, parse_lockfile, parse_pytest_targets};
+    use indexmap::IndexMap;
+
+    use super::{format_display_version, lockfile_problems, parse_lockfile, parse_pytest_targets};
 
     #[test]
     fn parse_pytest_targets_keeps_pytest_node_id() {
@@ -779,6 +939,31 @@ pytest==1.0.0
         assert_eq!(resolved.get("colorama"), Some(&"0.4.6".to_string()));
     }
 
+    #[test]
+    fn parse_lockfile_keeps_direct_references() {
+        let content = "\
+ddtrace @ git+https://github.com/DataDog/dd-trace-py@v2.0#egg=ddtrace ; python_version >= \"3.8\"
+    # via -r requirements.in
+pytest==8.0.0
+";
+        let resolved = parse_lockfile(content);
+        assert_eq!(
+            resolved.get("ddtrace").map(String::as_str),
+            Some("@ git+https://github.com/DataDog/dd-trace-py@v2.0#egg=ddtrace")
+        );
+
+        let pkgs: IndexMap<String, String> = [
+            (
+                "ddtrace".to_string(),
+                " @ git+https://github.com/DataDog/dd-trace-py@v2.0".to_string(),
+            ),
+            ("pytest".to_string(), ">=8".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        assert!(lockfile_problems(&pkgs, &resolved).is_empty());
+    }
+
     #[test]
     fn display_version_resolved_with_constraint() {
         assert_eq!(
@@ -801,4 +986,20 @@ pytest==1.0.0
     fn display_version_no_resolved_no_constraint() {
         assert_eq!(format_display_version("", None), "(latest)");
     }
+
+    #[test]
+    fn lockfile_problems_reports_missing_and_out_of_range_pins() {
+        let pkgs: IndexMap<String, String> = [
+            ("pytest".to_string(), ">=8".to_string()),
+            ("Flask".to_string(), "~=2.0".to_string()),
+            ("requests".to_string(), String::new()),
+        ]
+        .into_iter()
+        .collect();
+        let resolved = parse_lockfile("pytest==7.4.0\nflask==2.3.1\n");
+
+        let problems = lockfile_problems(&pkgs, &resolved);
+        let packages: Vec<&str> = problems.iter().map(|p| p.package.as_str()).collect();
+        assert_eq!(packages, vec!["pytest", "requests"]);
+    }
 }